@@ -0,0 +1,331 @@
+//! Summaries of the assets in a SerializedFile, read through its type trees,
+//! for reviewing what a bundle contains before it is uploaded.
+
+use std::collections::HashSet;
+
+use serde::Serialize;
+
+use crate::serialized::{ObjectInfo, SerializedFile, Value};
+
+const MATERIAL_CLASS_ID: i32 = 21;
+const MESH_RENDERER_CLASS_ID: i32 = 23;
+const TEXTURE2D_CLASS_ID: i32 = 28;
+const MESH_CLASS_ID: i32 = 43;
+const SHADER_CLASS_ID: i32 = 48;
+const SKINNED_MESH_RENDERER_CLASS_ID: i32 = 137;
+
+/// `MeshTopology` values that draw faces.
+const TOPOLOGY_TRIANGLES: i64 = 0;
+const TOPOLOGY_QUADS: i64 = 2;
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextureInfo {
+    pub name: String,
+    pub path_id: i64,
+    pub width: u32,
+    pub height: u32,
+    /// Unity's `TextureFormat` name, e.g. `DXT5` or `ASTC_6x6`.
+    pub format: Option<&'static str>,
+    pub format_code: i32,
+    pub mip_count: u32,
+    /// GPU memory for all mip levels, `None` for a format this crate
+    /// doesn't know the block size of.
+    pub vram_size: Option<u64>,
+}
+
+/// Block width, block height and bytes per block of each `TextureFormat`.
+/// Crunched formats are listed with the format they decompress to on load.
+fn texture_format(code: i32) -> Option<(&'static str, u32, u32, u64)> {
+    Some(match code {
+        1 => ("Alpha8", 1, 1, 1),
+        2 => ("ARGB4444", 1, 1, 2),
+        3 => ("RGB24", 1, 1, 3),
+        4 => ("RGBA32", 1, 1, 4),
+        5 => ("ARGB32", 1, 1, 4),
+        7 => ("RGB565", 1, 1, 2),
+        9 => ("R16", 1, 1, 2),
+        10 => ("DXT1", 4, 4, 8),
+        12 => ("DXT5", 4, 4, 16),
+        13 => ("RGBA4444", 1, 1, 2),
+        14 => ("BGRA32", 1, 1, 4),
+        15 => ("RHalf", 1, 1, 2),
+        16 => ("RGHalf", 1, 1, 4),
+        17 => ("RGBAHalf", 1, 1, 8),
+        18 => ("RFloat", 1, 1, 4),
+        19 => ("RGFloat", 1, 1, 8),
+        20 => ("RGBAFloat", 1, 1, 16),
+        22 => ("RGB9e5Float", 1, 1, 4),
+        24 => ("BC6H", 4, 4, 16),
+        25 => ("BC7", 4, 4, 16),
+        26 => ("BC4", 4, 4, 8),
+        27 => ("BC5", 4, 4, 16),
+        28 => ("DXT1Crunched", 4, 4, 8),
+        29 => ("DXT5Crunched", 4, 4, 16),
+        34 => ("ETC_RGB4", 4, 4, 8),
+        41 => ("EAC_R", 4, 4, 8),
+        42 => ("EAC_R_SIGNED", 4, 4, 8),
+        43 => ("EAC_RG", 4, 4, 16),
+        44 => ("EAC_RG_SIGNED", 4, 4, 16),
+        45 => ("ETC2_RGB", 4, 4, 8),
+        46 => ("ETC2_RGBA1", 4, 4, 8),
+        47 => ("ETC2_RGBA8", 4, 4, 16),
+        48 => ("ASTC_4x4", 4, 4, 16),
+        49 => ("ASTC_5x5", 5, 5, 16),
+        50 => ("ASTC_6x6", 6, 6, 16),
+        51 => ("ASTC_8x8", 8, 8, 16),
+        52 => ("ASTC_10x10", 10, 10, 16),
+        53 => ("ASTC_12x12", 12, 12, 16),
+        62 => ("RG16", 1, 1, 2),
+        63 => ("R8", 1, 1, 1),
+        64 => ("ETC_RGB4Crunched", 4, 4, 8),
+        65 => ("ETC2_RGBA8Crunched", 4, 4, 16),
+        72 => ("RG32", 1, 1, 4),
+        73 => ("RGB48", 1, 1, 6),
+        74 => ("RGBA64", 1, 1, 8),
+        _ => return None,
+    })
+}
+
+fn vram_size(width: u32, height: u32, mip_count: u32, format_code: i32) -> Option<u64> {
+    let (_, block_width, block_height, block_bytes) = texture_format(format_code)?;
+    let mut size = 0;
+    for level in 0..mip_count.clamp(1, 32) {
+        let level_width = (width >> level).max(1);
+        let level_height = (height >> level).max(1);
+        let blocks =
+            level_width.div_ceil(block_width) as u64 * level_height.div_ceil(block_height) as u64;
+        size += blocks * block_bytes;
+    }
+    Some(size)
+}
+
+fn int_field(value: &Value, name: &str) -> Option<i64> {
+    value.get(name)?.as_i64()
+}
+
+fn read_texture(serialized: &SerializedFile, object: &ObjectInfo) -> Option<TextureInfo> {
+    let value = serialized.read_object(object).ok()?;
+    let width = int_field(&value, "m_Width")? as u32;
+    let height = int_field(&value, "m_Height")? as u32;
+    let format_code = int_field(&value, "m_TextureFormat")? as i32;
+    // Before Unity 5.2 textures only recorded whether they had mipmaps
+    let mip_count = match int_field(&value, "m_MipCount") {
+        Some(count) => count as u32,
+        None if int_field(&value, "m_MipMap") == Some(1) => {
+            u32::BITS - width.max(height).max(1).leading_zeros()
+        }
+        None => 1,
+    };
+    Some(TextureInfo {
+        name: value
+            .get("m_Name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+        path_id: object.path_id,
+        width,
+        height,
+        format: texture_format(format_code).map(|(name, ..)| name),
+        format_code,
+        mip_count,
+        vram_size: vram_size(width, height, mip_count, format_code),
+    })
+}
+
+/// Every Texture2D in the file that has a type tree.
+pub fn textures(serialized: &SerializedFile) -> Vec<TextureInfo> {
+    serialized
+        .objects
+        .iter()
+        .filter(|object| object.class_id == TEXTURE2D_CLASS_ID)
+        .filter_map(|object| read_texture(serialized, object))
+        .collect()
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshInfo {
+    pub name: String,
+    pub path_id: i64,
+    pub vertex_count: u64,
+    /// Quads count as two triangles; lines and points as none.
+    pub triangle_count: u64,
+    pub submesh_count: u32,
+    pub bone_count: u32,
+    pub blend_shape_count: u32,
+}
+
+/// The figures VRChat's performance rank is based on, over every file
+/// added to it.
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MeshStats {
+    pub meshes: Vec<MeshInfo>,
+    pub triangle_count: u64,
+    pub mesh_renderer_count: u32,
+    pub skinned_mesh_renderer_count: u32,
+    /// Distinct transforms used as bones by the skinned mesh renderers.
+    pub bone_count: u32,
+    /// Paths of the SerializedFiles in a format we can't parse, which
+    /// aren't counted.
+    pub skipped: Vec<String>,
+}
+
+fn triangle_count(submesh: &Value) -> u64 {
+    let index_count = int_field(submesh, "indexCount").unwrap_or_default() as u64;
+    match int_field(submesh, "topology") {
+        Some(TOPOLOGY_TRIANGLES) => index_count / 3,
+        Some(TOPOLOGY_QUADS) => index_count / 4 * 2,
+        _ => 0,
+    }
+}
+
+fn read_mesh(serialized: &SerializedFile, object: &ObjectInfo) -> Option<MeshInfo> {
+    let value = serialized.read_object(object).ok()?;
+    let submeshes = value.get("m_SubMeshes")?.as_array()?;
+    // Meshes without bone name hashes predate them and list bind poses only
+    let bone_count = value
+        .get("m_BoneNameHashes")
+        .or_else(|| value.get("m_BindPose"))
+        .and_then(Value::element_count)
+        .unwrap_or_default();
+    let blend_shape_count = value
+        .get("m_Shapes")
+        .and_then(|shapes| shapes.get("shapes"))
+        .and_then(Value::element_count)
+        .unwrap_or_default();
+    Some(MeshInfo {
+        name: value
+            .get("m_Name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned(),
+        path_id: object.path_id,
+        vertex_count: value
+            .get("m_VertexData")
+            .and_then(|data| int_field(data, "m_VertexCount"))
+            .unwrap_or_default() as u64,
+        triangle_count: submeshes.iter().map(triangle_count).sum(),
+        submesh_count: submeshes.len() as u32,
+        bone_count: bone_count as u32,
+        blend_shape_count: blend_shape_count as u32,
+    })
+}
+
+impl MeshStats {
+    /// Adds the meshes and renderers in `serialized`.
+    pub fn add(&mut self, serialized: &SerializedFile) {
+        let mut bones = HashSet::new();
+        for object in &serialized.objects {
+            match object.class_id {
+                MESH_CLASS_ID => {
+                    if let Some(mesh) = read_mesh(serialized, object) {
+                        self.triangle_count += mesh.triangle_count;
+                        self.meshes.push(mesh);
+                    }
+                }
+                MESH_RENDERER_CLASS_ID => self.mesh_renderer_count += 1,
+                SKINNED_MESH_RENDERER_CLASS_ID => {
+                    self.skinned_mesh_renderer_count += 1;
+                    let Ok(value) = serialized.read_object(object) else {
+                        continue;
+                    };
+                    let renderer_bones = value.get("m_Bones").and_then(Value::as_array);
+                    bones.extend(
+                        renderer_bones
+                            .unwrap_or_default()
+                            .iter()
+                            .filter_map(|bone| {
+                                Some((int_field(bone, "m_FileID")?, int_field(bone, "m_PathID")?))
+                            }),
+                    );
+                }
+                _ => {}
+            }
+        }
+        self.bone_count += bones.len() as u32;
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ShaderInfo {
+    pub name: String,
+    pub path_id: i64,
+    /// Serialized size, which grows with the shader's variants.
+    pub size: u32,
+    /// Materials in the same file that use the shader.
+    pub material_count: u32,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MaterialStats {
+    pub shaders: Vec<ShaderInfo>,
+    pub material_count: u32,
+    /// Materials whose shader lives in another file, such as Unity's
+    /// built-in shaders, and so isn't listed.
+    pub external_shader_material_count: u32,
+    /// Paths of the SerializedFiles in a format we can't parse, whose
+    /// shaders and materials aren't listed.
+    pub skipped: Vec<String>,
+}
+
+/// Shaders keep their name in the parsed form since Unity 2019.
+fn shader_name(value: &Value) -> Option<&str> {
+    value
+        .get("m_ParsedForm")
+        .and_then(|form| form.get("m_Name"))
+        .or_else(|| value.get("m_Name"))
+        .and_then(Value::as_str)
+}
+
+impl MaterialStats {
+    /// Adds the shaders and materials in `serialized`.
+    pub fn add(&mut self, serialized: &SerializedFile) {
+        let first_shader = self.shaders.len();
+        let mut material_shaders = Vec::new();
+        for object in &serialized.objects {
+            match object.class_id {
+                SHADER_CLASS_ID => self.shaders.push(ShaderInfo {
+                    name: serialized
+                        .read_object(object)
+                        .ok()
+                        .as_ref()
+                        .and_then(shader_name)
+                        .unwrap_or_default()
+                        .to_owned(),
+                    path_id: object.path_id,
+                    size: object.byte_size,
+                    material_count: 0,
+                }),
+                MATERIAL_CLASS_ID => {
+                    self.material_count += 1;
+                    let shader = serialized.read_object(object).ok().and_then(|value| {
+                        let shader = value.get("m_Shader")?;
+                        Some((
+                            int_field(shader, "m_FileID")?,
+                            int_field(shader, "m_PathID")?,
+                        ))
+                    });
+                    match shader {
+                        Some((0, path_id)) => material_shaders.push(path_id),
+                        Some(_) => self.external_shader_material_count += 1,
+                        None => {}
+                    }
+                }
+                _ => {}
+            }
+        }
+        // Materials may come before their shader in the object table
+        for path_id in material_shaders {
+            if let Some(shader) = self.shaders[first_shader..]
+                .iter_mut()
+                .find(|shader| shader.path_id == path_id)
+            {
+                shader.material_count += 1;
+            }
+        }
+    }
+}
@@ -1,47 +1,367 @@
 use liblzma::read::XzDecoder;
 use liblzma::stream::{self, Filters, Stream};
 use liblzma::write::XzEncoder;
-use lz4_flex::block::DecompressError;
+use md5::{Digest, Md5};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::borrow::Cow;
 use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Chain, Cursor, Read, Seek, SeekFrom, Write};
-use std::time::Instant;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 use thiserror::Error;
+use tokio_util::sync::CancellationToken;
 
-// TODO: Enforce limits
 const PC_COMPRESSED_SIZE_LIMIT: usize = 200 * 1024 * 1024;
 const PC_UNCOMPRESSED_SIZE_LIMIT: usize = 500 * 1024 * 1024;
 const ANDROID_COMPRESSED_SIZE_LIMIT: usize = 10 * 1024 * 1024;
 const ANDROID_UNCOMPRESSED_SIZE_LIMIT: usize = 40 * 1024 * 1024;
 
+const LZMA_MIN_DICT_SIZE: u32 = 512 * 1024;
+const LZMA_MAX_DICT_SIZE: u32 = 64 * 1024 * 1024;
+
+pub const LZ4HC_DEFAULT_LEVEL: i32 = 9;
+const LZ4HC_MAX_LEVEL: i32 = 12;
+
+/// UnityFS versions the encoder writes back faithfully: 6 from Unity 5.3,
+/// 7 adding header alignment in 2019.4 and 8 from 2022.1, laid out like 7.
+const SUPPORTED_FS_VERSIONS: std::ops::RangeInclusive<u32> = 6..=8;
+
+/// Whether a UnityFS header of `version` is padded to 16 bytes. Decoding
+/// and encoding both go through this so they can't disagree.
+fn aligns_header(version: u32) -> bool {
+    version >= 7
+}
+
+/// Unity 2020.3.34, 2021.3.2 and 2022.1.1 moved the encryption flag from
+/// 0x200 to 0x400 and reused 0x200 for padding after the block info.
+/// Bundles with a stripped revision like "0.0.0" are taken to be new.
+fn uses_new_archive_flags(unity_revision: &str) -> bool {
+    let mut parts = unity_revision
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().unwrap_or(0));
+    let mut next = || parts.next().unwrap_or(0);
+    let version = (next(), next(), next());
+    match version.0 {
+        0 => true,
+        1..=2019 => false,
+        2020 => version >= (2020, 3, 34),
+        2021 => version >= (2021, 3, 2),
+        2022 => version >= (2022, 1, 1),
+        _ => true,
+    }
+}
+
+/// Whether the block data starts 16-byte aligned after the block info.
+fn pads_block_info(flags: u32, unity_revision: &str) -> bool {
+    flags & 0x200 != 0 && uses_new_archive_flags(unity_revision)
+}
+
+fn is_encrypted(flags: u32, unity_revision: &str) -> bool {
+    let flag = if uses_new_archive_flags(unity_revision) {
+        0x400
+    } else {
+        0x200
+    };
+    flags & flag != 0
+}
+
+/// Blocks processed at once by default. Each one in flight holds a whole
+/// uncompressed block, so this stays small even on many-core machines.
+const MAX_DEFAULT_THREADS: usize = 4;
+
+fn default_threads() -> usize {
+    std::thread::available_parallelism()
+        .map_or(1, |threads| threads.get())
+        .min(MAX_DEFAULT_THREADS)
+}
+
 #[derive(Error, Debug)]
 pub enum BundleError {
     #[error("IO error: {0}")]
     Io(#[from] io::Error),
     #[error("Unsupported bundle type: {0}")]
     UnsupportedBundle(String),
+    #[error("{0} bundles can't be read or transcoded")]
+    UnsupportedFormat(&'static str),
+    #[error("{0} bundles need converting to UnityFS first")]
+    NeedsConversion(&'static str),
     #[error("Invalid data: {0}")]
     InvalidData(String),
-    #[error("Decompress error")]
-    Decompress(#[from] DecompressError),
+    #[error("Decompress error: {0}")]
+    Decompress(io::Error),
     #[error("LZMA error")]
     LZMA(#[from] liblzma::stream::Error),
     #[error("File not in Directory Info")]
     DirNotFound,
-    #[error("More than one block in AssetBundle")]
-    MoreThanOneBlock,
+    #[error("Block index {0} out of range ({1} blocks)")]
+    BlockOutOfRange(usize, usize),
+    #[error(
+        "{kind} size of {actual} bytes exceeds the limit of {limit} bytes by {} bytes",
+        .actual - .limit
+    )]
+    SizeLimitExceeded {
+        kind: &'static str,
+        actual: u64,
+        limit: u64,
+    },
+    #[error("Cancelled")]
+    Cancelled,
 }
 
 type Result<T> = std::result::Result<T, BundleError>;
 
-#[derive(Debug, PartialEq, Eq)]
+/// Compression types stored in the low 6 bits of the archive and block flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Lzma,
+    Lz4,
+    Lz4Hc,
+    Zstd,
+}
+
+impl Compression {
+    pub const ALL: [Compression; 5] = [
+        Compression::None,
+        Compression::Lzma,
+        Compression::Lz4,
+        Compression::Lz4Hc,
+        Compression::Zstd,
+    ];
+
+    pub fn from_flags(flags: u32) -> Option<Self> {
+        match flags & 0x3F {
+            0 => Some(Compression::None),
+            1 => Some(Compression::Lzma),
+            2 => Some(Compression::Lz4),
+            3 => Some(Compression::Lz4Hc),
+            4 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn flags(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Lzma => 1,
+            Compression::Lz4 => 2,
+            Compression::Lz4Hc => 3,
+            Compression::Zstd => 4,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Lzma => "lzma",
+            Compression::Lz4 => "lz4",
+            Compression::Lz4Hc => "lz4hc",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = BundleError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Compression::ALL
+            .into_iter()
+            .find(|c| c.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| BundleError::InvalidData(format!("Unknown compression type: {}", s)))
+    }
+}
+
+/// Archive layouts, told apart by the signature at the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    UnityFs,
+    /// Uncompressed pre-5.3 layout, which is read and then written as
+    /// UnityFS.
+    UnityRaw,
+    /// UnityRaw with its data in one `.lzma` stream, also written as
+    /// UnityFS.
+    UnityWeb,
+    UnityArchive,
+}
+
+impl ArchiveFormat {
+    pub fn from_signature(signature: &str) -> Option<Self> {
+        match signature {
+            "UnityFS" => Some(ArchiveFormat::UnityFs),
+            "UnityRaw" => Some(ArchiveFormat::UnityRaw),
+            "UnityWeb" => Some(ArchiveFormat::UnityWeb),
+            "UnityArchive" => Some(ArchiveFormat::UnityArchive),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveFormat::UnityFs => "UnityFS",
+            ArchiveFormat::UnityRaw => "UnityRaw",
+            ArchiveFormat::UnityWeb => "UnityWeb",
+            ArchiveFormat::UnityArchive => "UnityArchive",
+        }
+    }
+
+    /// Whether `AssetBundleDecoder` can read bundles in this format.
+    pub fn can_decode(self) -> bool {
+        matches!(
+            self,
+            ArchiveFormat::UnityFs | ArchiveFormat::UnityRaw | ArchiveFormat::UnityWeb
+        )
+    }
+
+    /// Whether `AssetBundleEncoder` can write bundles in this format.
+    pub fn can_encode(self) -> bool {
+        self == ArchiveFormat::UnityFs
+    }
+}
+
+/// Upload target, which decides the size limits a bundle must respect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Pc,
+    Android,
+}
+
+impl Platform {
+    pub fn compressed_size_limit(self) -> u64 {
+        match self {
+            Platform::Pc => PC_COMPRESSED_SIZE_LIMIT as u64,
+            Platform::Android => ANDROID_COMPRESSED_SIZE_LIMIT as u64,
+        }
+    }
+
+    pub fn uncompressed_size_limit(self) -> u64 {
+        match self {
+            Platform::Pc => PC_UNCOMPRESSED_SIZE_LIMIT as u64,
+            Platform::Android => ANDROID_UNCOMPRESSED_SIZE_LIMIT as u64,
+        }
+    }
+}
+
+impl FromStr for Platform {
+    type Err = BundleError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "pc" => Ok(Platform::Pc),
+            "android" => Ok(Platform::Android),
+            _ => Err(BundleError::InvalidData(format!("Unknown platform: {}", s))),
+        }
+    }
+}
+
+fn check_size_limit(kind: &'static str, actual: u64, limit: u64) -> Result<()> {
+    if actual > limit {
+        return Err(BundleError::SizeLimitExceeded {
+            kind,
+            actual,
+            limit,
+        });
+    }
+    Ok(())
+}
+
+/// Sizes, flags and the path terminator of the smallest directory entry.
+const MIN_DIRECTORY_INFO_LEN: u64 = 8 + 8 + 4 + 1;
+const MIN_RAW_DIRECTORY_INFO_LEN: u64 = 1 + 4 + 4;
+const BLOCK_INFO_LEN: u64 = 4 + 4 + 2;
+
+/// Rejects an entry count that can't fit in the `available` bytes left,
+/// before anything is allocated for the entries.
+fn check_entry_count(kind: &str, count: u32, entry_len: u64, available: u64) -> Result<()> {
+    if u64::from(count) * entry_len > available {
+        return Err(BundleError::InvalidData(format!(
+            "{} count of {} doesn't fit in the remaining {} bytes",
+            kind, count, available
+        )));
+    }
+    Ok(())
+}
+
+/// Rejects LZMA properties that liblzma would choke on or that no encoder
+/// produces, so a corrupt block fails with a readable error. The props byte
+/// packs `(pb * 5 + lp) * 9 + lc` with lc < 9, lp < 5 and pb < 5. Small
+/// dictionaries are valid for other tools' output, so only the upper bound
+/// of the encoder's clamp is enforced.
+fn check_lzma_properties(header: &[u8; 5]) -> Result<()> {
+    let props = header[0];
+    if props >= 9 * 5 * 5 {
+        return Err(BundleError::InvalidData(format!(
+            "LZMA properties byte {:#04x} is out of range",
+            props
+        )));
+    }
+    let dict_size = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+    if dict_size > LZMA_MAX_DICT_SIZE {
+        return Err(BundleError::InvalidData(format!(
+            "LZMA dictionary size of {} bytes exceeds {} bytes",
+            dict_size, LZMA_MAX_DICT_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Offsets and sizes index into the concatenated block data, so an entry
+/// reaching past it would be read from garbage.
+fn check_entry_bounds(dir_info: &DirectoryInfo, data_len: u64) -> Result<()> {
+    if dir_info
+        .offset
+        .checked_add(dir_info.size)
+        .map_or(true, |end| end > data_len)
+    {
+        return Err(BundleError::InvalidData(format!(
+            "Directory entry {} runs past the {} bytes of block data",
+            dir_info.path, data_len
+        )));
+    }
+    Ok(())
+}
+
+fn blocks_hash(blocks: &[Vec<u8>]) -> [u8; 16] {
+    let mut hasher = Md5::new();
+    for block in blocks {
+        hasher.update(block);
+    }
+    hasher.finalize().into()
+}
+
+fn remaining_len(reader: &Cursor<Vec<u8>>) -> u64 {
+    (reader.get_ref().len() as u64).saturating_sub(reader.position())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct BlockInfo {
     uncompressed_size: u32,
     compressed_size: u32,
     flags: u16,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+/// Block info entries are read and written only through this pair, so the
+/// field order and widths (`BLOCK_INFO_LEN` bytes, big-endian) can't drift
+/// apart between decode and encode.
+impl BlockInfo {
+    fn read_from<R: Read>(reader: &mut R) -> io::Result<Self> {
+        Ok(BlockInfo {
+            uncompressed_size: reader.read_u32()?,
+            compressed_size: reader.read_u32()?,
+            flags: reader.read_u16()?,
+        })
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32(self.uncompressed_size)?;
+        writer.write_u32(self.compressed_size)?;
+        writer.write_u16(self.flags)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DirectoryInfo {
     offset: u64,
     size: u64,
@@ -58,48 +378,721 @@ pub struct AssetBundle {
     size: u64,
     compressed_block_info_size: u32,
     uncompressed_block_info_size: u32,
+    /// Archive flags. Encoding writes back every bit, including ones this
+    /// crate doesn't know, and only changes the compression in the low 6.
     flags: u32,
+    /// Raw bytes between the last known header field and the aligned start
+    /// of the block info, written back unchanged on encode.
+    header_extra: Vec<u8>,
+    /// Hash of the uncompressed block data stored ahead of the block info,
+    /// all zero when the writer left it unset. Unity fills it with its own
+    /// Hash128, so it is kept as read and never checked. Encoding writes an
+    /// MD5 of the data instead.
+    data_hash: [u8; 16],
     blocks_info: Vec<BlockInfo>,
     directory_info: Vec<DirectoryInfo>,
-    block: Vec<u8>,
+    /// Decompressed data of each block, in the order of `blocks_info`.
+    /// Directory offsets index into their concatenation.
+    blocks: Vec<Vec<u8>>,
+}
+
+impl DirectoryInfo {
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    /// Whether the entry is a SerializedFile rather than a resource blob.
+    pub fn is_serialized_file(&self) -> bool {
+        self.flags & 0x4 != 0
+    }
 }
 
 impl AssetBundle {
+    pub fn directory_info(&self) -> &[DirectoryInfo] {
+        &self.directory_info
+    }
+
+    pub fn format(&self) -> Option<ArchiveFormat> {
+        ArchiveFormat::from_signature(&self.signature)
+    }
+
+    /// Turns a bundle read from UnityRaw or UnityWeb into a UnityFS one.
+    /// Their data already forms a single block the directory offsets index
+    /// into, so only the header changes, and entries without a resource
+    /// extension are flagged as serialized files, which the legacy
+    /// directory doesn't record. Other bundles are left alone.
+    pub fn convert_to_unity_fs(&mut self) {
+        if !matches!(
+            self.format(),
+            Some(ArchiveFormat::UnityRaw | ArchiveFormat::UnityWeb)
+        ) {
+            return;
+        }
+        self.signature = ArchiveFormat::UnityFs.name().to_owned();
+        // The layout of Unity 5.3, the first to write UnityFS
+        self.version = *SUPPORTED_FS_VERSIONS.start();
+        self.unity_version = "5.x.x".to_owned();
+        self.flags = 0x40 | Compression::Lz4Hc.flags();
+        self.header_extra.clear();
+        for entry in &mut self.directory_info {
+            if !entry.path.ends_with(".resS") && !entry.path.ends_with(".resource") {
+                entry.flags |= 0x4;
+            }
+        }
+    }
+
+    /// A copy of everything but the block data.
+    fn metadata(&self) -> AssetBundle {
+        AssetBundle {
+            signature: self.signature.clone(),
+            version: self.version,
+            unity_version: self.unity_version.clone(),
+            unity_revision: self.unity_revision.clone(),
+            size: self.size,
+            compressed_block_info_size: self.compressed_block_info_size,
+            uncompressed_block_info_size: self.uncompressed_block_info_size,
+            flags: self.flags,
+            header_extra: self.header_extra.clone(),
+            data_hash: self.data_hash,
+            blocks_info: self.blocks_info.clone(),
+            directory_info: self.directory_info.clone(),
+            blocks: Vec::new(),
+        }
+    }
+
+    /// Fails for bundles read from a format the encoder can't write.
+    fn check_encodable(&self) -> Result<()> {
+        match self.format() {
+            Some(format) if !format.can_encode() => {
+                Err(BundleError::NeedsConversion(format.name()))
+            }
+            Some(_) if !SUPPORTED_FS_VERSIONS.contains(&self.version) => {
+                Err(BundleError::UnsupportedBundle(format!(
+                    "{} version {}, only versions {} to {} can be transcoded",
+                    self.signature,
+                    self.version,
+                    SUPPORTED_FS_VERSIONS.start(),
+                    SUPPORTED_FS_VERSIONS.end()
+                )))
+            }
+            Some(_) => Ok(()),
+            None => Err(BundleError::UnsupportedBundle(self.signature.clone())),
+        }
+    }
+
+    pub fn info(&self) -> BundleInfo {
+        BundleInfo {
+            signature: self.signature.clone(),
+            version: self.version,
+            unity_version: self.unity_version.clone(),
+            unity_revision: self.unity_revision.clone(),
+            size: self.size,
+            flags: self.flags,
+            block_info_compression: Compression::from_flags(self.flags).map(Compression::name),
+            compressed_size: self
+                .blocks_info
+                .iter()
+                .map(|block| u64::from(block.compressed_size))
+                .sum(),
+            uncompressed_size: self
+                .blocks_info
+                .iter()
+                .map(|block| u64::from(block.uncompressed_size))
+                .sum(),
+            blocks: self
+                .blocks_info
+                .iter()
+                .map(|block| BlockMeta {
+                    uncompressed_size: block.uncompressed_size,
+                    compressed_size: block.compressed_size,
+                    flags: block.flags,
+                    compression: Compression::from_flags(block.flags.into()).map(Compression::name),
+                    compression_code: block.flags & 0x3F,
+                    ratio: (block.uncompressed_size > 0).then(|| {
+                        f64::from(block.compressed_size) / f64::from(block.uncompressed_size)
+                    }),
+                })
+                .collect(),
+            directory: self
+                .directory_info
+                .iter()
+                .map(|entry| DirectoryMeta {
+                    path: entry.path.clone(),
+                    offset: entry.offset,
+                    size: entry.size,
+                    flags: entry.flags,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn find_entry(&self, path: &str) -> Result<&DirectoryInfo> {
+        self.directory_info
+            .iter()
+            .find(|entry| entry.path == path)
+            .ok_or(BundleError::DirNotFound)
+    }
+
+    /// The bytes of a directory entry within the decompressed data, or
+    /// `None` if the entry points outside of it. Entries spanning more than
+    /// one block are copied together.
+    pub fn entry_data(&self, entry: &DirectoryInfo) -> Option<Cow<'_, [u8]>> {
+        let mut start = usize::try_from(entry.offset).ok()?;
+        let mut remaining = usize::try_from(entry.size).ok()?;
+
+        let mut blocks = self.blocks.iter();
+        let first = loop {
+            let block = blocks.next()?;
+            if start < block.len() || (remaining == 0 && start == block.len()) {
+                break block;
+            }
+            start -= block.len();
+        };
+        if let Some(data) = first.get(start..start.checked_add(remaining)?) {
+            return Some(Cow::Borrowed(data));
+        }
+
+        let mut data = Vec::with_capacity(remaining);
+        data.extend_from_slice(&first[start..]);
+        remaining -= first.len() - start;
+        while remaining > 0 {
+            let block = blocks.next()?;
+            let len = remaining.min(block.len());
+            data.extend_from_slice(&block[..len]);
+            remaining -= len;
+        }
+        Some(Cow::Owned(data))
+    }
+
+    /// Replaces the bytes of the directory entry at `path` with `data` and
+    /// moves the entries after it. Only the blocks the entry lies in are
+    /// rebuilt, cut to the size of the largest block and keeping the flags
+    /// of the first; blocks holding only other entries stay as they are.
+    /// Compressed sizes are recomputed when the bundle is encoded.
+    pub fn replace_entry(&mut self, path: &str, data: Vec<u8>) -> Result<()> {
+        let index = self
+            .directory_info
+            .iter()
+            .position(|entry| entry.path == path)
+            .ok_or(BundleError::DirNotFound)?;
+        // Also keeps the ends of the other entries from overflowing below
+        let data_len = self.blocks.iter().map(|block| block.len() as u64).sum();
+        for entry in &self.directory_info {
+            check_entry_bounds(entry, data_len)?;
+        }
+        let entry = &self.directory_info[index];
+        let (start, end) = (entry.offset, entry.offset + entry.size);
+        if self
+            .directory_info
+            .iter()
+            .enumerate()
+            .any(|(other, entry)| {
+                other != index && entry.offset < end && start < entry.offset + entry.size
+            })
+        {
+            return Err(BundleError::InvalidData(format!(
+                "Entry {} overlaps another entry",
+                path
+            )));
+        }
+
+        // Blocks holding the first and the last byte of the entry, and
+        // where the first of them starts in the data
+        let mut block_start = 0;
+        let mut first = None;
+        let mut last = None;
+        for (block_index, block) in self.blocks.iter().enumerate() {
+            let block_end = block_start + block.len() as u64;
+            let is_last = block_index + 1 == self.blocks.len();
+            if first.is_none() && (start < block_end || (is_last && start == block_end)) {
+                first = Some((block_index, block_start));
+            }
+            if first.is_some() && (end <= block_end || is_last) {
+                last = Some(block_index);
+                break;
+            }
+            block_start = block_end;
+        }
+        let (Some((first, region_start)), Some(last)) = (first, last) else {
+            return Err(BundleError::InvalidData(format!(
+                "Entry {} lies outside the decompressed data",
+                path
+            )));
+        };
+        let mut region = self.blocks[first..=last].concat();
+        let range = (start - region_start) as usize..(end - region_start) as usize;
+        if range.end > region.len() {
+            return Err(BundleError::InvalidData(format!(
+                "Entry {} lies outside the decompressed data",
+                path
+            )));
+        }
+        let new_len = data.len() as u64;
+        region.splice(range, data);
+
+        let block_size = self.blocks.iter().map(Vec::len).max().unwrap_or(0).max(1);
+        let flags = self.blocks_info[first].flags;
+        let blocks: Vec<Vec<u8>> = region.chunks(block_size).map(<[u8]>::to_vec).collect();
+        let blocks_info: Vec<BlockInfo> = blocks
+            .iter()
+            .map(|block| BlockInfo {
+                uncompressed_size: block.len() as u32,
+                compressed_size: 0,
+                flags,
+            })
+            .collect();
+        self.blocks.splice(first..=last, blocks);
+        self.blocks_info.splice(first..=last, blocks_info);
+
+        for (other, entry) in self.directory_info.iter_mut().enumerate() {
+            if other == index {
+                entry.size = new_len;
+            } else if entry.offset >= end {
+                entry.offset = entry.offset - end + start + new_len;
+            }
+        }
+        Ok(())
+    }
+
     pub fn set_blocks_lzma(&mut self) {
+        self.set_blocks_compression(Compression::Lzma);
+    }
+
+    pub fn set_blocks_zstd(&mut self) {
+        self.set_blocks_compression(Compression::Zstd);
+    }
+
+    pub fn set_blocks_compression(&mut self, compression: Compression) {
         for block in &mut self.blocks_info {
-            block.flags = (block.flags & !0x3F) | 1;
+            block.flags = (block.flags & !0x3F) | compression.flags() as u16;
+        }
+    }
+
+    /// Sets how the block info region is compressed, independently of the
+    /// data blocks.
+    pub fn set_block_info_compression(&mut self, compression: Compression) {
+        self.flags = (self.flags & !0x3F) | compression.flags();
+    }
+
+    /// The first way `other` differs in content from this bundle, ignoring
+    /// what re-encoding legitimately changes: compression flags, compressed
+    /// sizes and the total size. Both bundles need their block data.
+    pub fn first_difference(&self, other: &AssetBundle) -> Option<BundleDifference> {
+        self.header_difference(other)
+            .or_else(|| {
+                difference(
+                    "blockCount",
+                    self.blocks_info.len(),
+                    other.blocks_info.len(),
+                )
+            })
+            .or_else(|| {
+                self.blocks_info
+                    .iter()
+                    .zip(&other.blocks_info)
+                    .enumerate()
+                    .find_map(|(index, (block, other_block))| {
+                        difference(
+                            format!("blocks[{}].uncompressedSize", index),
+                            block.uncompressed_size,
+                            other_block.uncompressed_size,
+                        )
+                        .or_else(|| {
+                            difference(
+                                format!("blocks[{}].flags", index),
+                                block.flags & !0x3F,
+                                other_block.flags & !0x3F,
+                            )
+                        })
+                    })
+            })
+            .or_else(|| self.directory_difference(other))
+            .or_else(|| {
+                self.blocks.iter().zip(&other.blocks).enumerate().find_map(
+                    |(index, (data, other_data))| {
+                        let offset = data
+                            .iter()
+                            .zip(other_data)
+                            .position(|(byte, other_byte)| byte != other_byte)?;
+                        Some(BundleDifference {
+                            field: format!("blocks[{}].data[{}]", index, offset),
+                            original: format!("{:#04x}", data[offset]),
+                            other: format!("{:#04x}", other_data[offset]),
+                        })
+                    },
+                )
+            })
+    }
+
+    /// Like `first_difference`, but also ignores how the data is split
+    /// into blocks, for bundles re-encoded with a different block size.
+    pub fn first_content_difference(&self, other: &AssetBundle) -> Option<BundleDifference> {
+        let data_len = |bundle: &AssetBundle| -> usize { bundle.blocks.iter().map(Vec::len).sum() };
+        self.header_difference(other)
+            .or_else(|| difference("dataSize", data_len(self), data_len(other)))
+            .or_else(|| self.directory_difference(other))
+            .or_else(|| {
+                let data = self.blocks.iter().flatten();
+                let other_data = other.blocks.iter().flatten();
+                data.zip(other_data)
+                    .enumerate()
+                    .find(|(_, (byte, other_byte))| byte != other_byte)
+                    .map(|(offset, (byte, other_byte))| BundleDifference {
+                        field: format!("data[{}]", offset),
+                        original: format!("{:#04x}", byte),
+                        other: format!("{:#04x}", other_byte),
+                    })
+            })
+    }
+
+    fn header_difference(&self, other: &AssetBundle) -> Option<BundleDifference> {
+        difference("signature", &self.signature, &other.signature)
+            .or_else(|| difference("version", self.version, other.version))
+            .or_else(|| difference("unityVersion", &self.unity_version, &other.unity_version))
+            .or_else(|| difference("unityRevision", &self.unity_revision, &other.unity_revision))
+            .or_else(|| difference("flags", self.flags & !0x3F, other.flags & !0x3F))
+    }
+
+    fn directory_difference(&self, other: &AssetBundle) -> Option<BundleDifference> {
+        difference(
+            "directoryCount",
+            self.directory_info.len(),
+            other.directory_info.len(),
+        )
+        .or_else(|| {
+            self.directory_info
+                .iter()
+                .zip(&other.directory_info)
+                .enumerate()
+                .find_map(|(index, (entry, other_entry))| {
+                    let field = |name: &str| format!("directory[{}].{}", index, name);
+                    difference(field("path"), &entry.path, &other_entry.path)
+                        .or_else(|| difference(field("offset"), entry.offset, other_entry.offset))
+                        .or_else(|| difference(field("size"), entry.size, other_entry.size))
+                        .or_else(|| difference(field("flags"), entry.flags, other_entry.flags))
+                })
+        })
+    }
+}
+
+/// A field whose value differs between two bundles, as compared by
+/// `AssetBundle::first_difference`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleDifference {
+    pub field: String,
+    pub original: String,
+    pub other: String,
+}
+
+fn difference<T: PartialEq + std::fmt::Debug>(
+    field: impl Into<String>,
+    original: T,
+    other: T,
+) -> Option<BundleDifference> {
+    (original != other).then(|| BundleDifference {
+        field: field.into(),
+        original: format!("{:?}", original),
+        other: format!("{:?}", other),
+    })
+}
+
+struct Header {
+    signature: String,
+    version: u32,
+    unity_version: String,
+    unity_revision: String,
+    size_offset: u64,
+    size: u64,
+    compressed_block_info_size: u32,
+    uncompressed_block_info_size: u32,
+    flags: u32,
+}
+
+/// Summary of a bundle's header, block info and directory info.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BundleInfo {
+    pub signature: String,
+    pub version: u32,
+    pub unity_version: String,
+    pub unity_revision: String,
+    pub size: u64,
+    pub flags: u32,
+    pub block_info_compression: Option<&'static str>,
+    /// Totals over all blocks, so the data size needs no summing.
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub blocks: Vec<BlockMeta>,
+    pub directory: Vec<DirectoryMeta>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockMeta {
+    pub uncompressed_size: u32,
+    pub compressed_size: u32,
+    pub flags: u16,
+    /// Name of the block's compression, `None` for a code this crate
+    /// doesn't know, which `compression_code` still reports.
+    pub compression: Option<&'static str>,
+    pub compression_code: u16,
+    /// Compressed size as a fraction of the uncompressed size, `None` for
+    /// an empty block.
+    pub ratio: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryMeta {
+    pub path: String,
+    pub offset: u64,
+    pub size: u64,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockTiming {
+    pub index: usize,
+    pub compression: Option<Compression>,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+    pub elapsed: Duration,
+}
+
+/// Outcome of one check made by `AssetBundleDecoder::validate`.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationCheck {
+    pub name: String,
+    pub passed: bool,
+    /// Why the check failed.
+    pub detail: Option<String>,
+}
+
+impl ValidationCheck {
+    fn new(name: String, result: Result<()>) -> Self {
+        Self {
+            name,
+            passed: result.is_ok(),
+            detail: result.err().map(|err| err.to_string()),
         }
     }
 }
 
+/// The header's total size field and its byte offset in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeField {
+    pub offset: u64,
+    pub declared: u64,
+}
+
+/// A run of alignment padding, located after the `kind` region.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PaddingRegion {
+    pub kind: &'static str,
+    pub offset: u64,
+    pub length: u64,
+    pub zeroed: bool,
+}
+
 pub struct AssetBundleDecoder<R: Read + Seek> {
     inner: R,
+    threads: usize,
+    /// Whether LZMA data carries the 8-byte size of an `.lzma` header.
+    lzma_alone: bool,
 }
 
 impl<R: Read + Seek> AssetBundleDecoder<R> {
     pub fn new(reader: R) -> Self {
-        Self { inner: reader }
+        Self {
+            inner: reader,
+            threads: default_threads(),
+            lzma_alone: false,
+        }
+    }
+
+    /// Most blocks `decode` decompresses at once, at least 1. Defaults to
+    /// the number of CPUs, up to 4.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
     }
 
     pub fn decode(mut self) -> Result<(AssetBundle)> {
-        let signature = self.inner.read_string()?;
-        if signature != "UnityFS" {
-            return Err(BundleError::UnsupportedBundle(signature));
+        let mut bundle = self.read_metadata()?;
+
+        // Blocks are stored back to back in the order of the block info.
+        // Each batch is read in order, then decompressed concurrently.
+        let mut blocks = Vec::with_capacity(bundle.blocks_info.len());
+        let lzma_alone = self.lzma_alone;
+        for batch in bundle.blocks_info.chunks(self.threads) {
+            let compressed = batch
+                .iter()
+                .map(|block_info| self.read_bytes(block_info.compressed_size))
+                .collect::<Result<Vec<_>>>()?;
+            let decompressed = batch
+                .par_iter()
+                .zip(compressed)
+                .map(|(block_info, data)| {
+                    AssetBundleDecoder::for_block(data, lzma_alone).read_decompress(
+                        block_info.compressed_size,
+                        block_info.uncompressed_size,
+                        block_info.flags.into(),
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?;
+            blocks.extend(decompressed);
         }
+        bundle.blocks = blocks;
 
-        let version = self.inner.read_u32()?;
-        let unity_version = self.inner.read_string()?;
-        let unity_revision = self.inner.read_string()?;
+        Ok(bundle)
+    }
 
-        let size = self.inner.read_u64()?;
-        let compressed_block_info_size = self.inner.read_u32()?;
-        let uncompressed_block_info_size = self.inner.read_u32()?;
-        let flags = self.inner.read_u32()?;
+    /// Reads the header, block info and directory info without
+    /// decompressing any block data.
+    pub fn decode_metadata(mut self) -> Result<AssetBundle> {
+        self.read_metadata()
+    }
 
-        if version >= 7 {
-            self.inner.align(16)?;
+    /// Decompresses only the block at `index`, skipping over the others.
+    pub fn decode_block(mut self, index: usize) -> Result<Vec<u8>> {
+        let bundle = self.read_metadata()?;
+        let block_info = bundle
+            .blocks_info
+            .get(index)
+            .ok_or(BundleError::BlockOutOfRange(
+                index,
+                bundle.blocks_info.len(),
+            ))?;
+
+        let skip: u64 = bundle.blocks_info[..index]
+            .iter()
+            .map(|block| block.compressed_size as u64)
+            .sum();
+        self.inner.seek(SeekFrom::Current(skip as i64))?;
+
+        self.read_decompress(
+            block_info.compressed_size,
+            block_info.uncompressed_size,
+            block_info.flags.into(),
+        )
+    }
+
+    /// Decompresses every block in turn, timing only the decompression of
+    /// data already read into memory.
+    pub fn time_blocks(mut self) -> Result<Vec<BlockTiming>> {
+        let bundle = self.read_metadata()?;
+        let mut timings = Vec::new();
+        for (index, block_info) in bundle.blocks_info.iter().enumerate() {
+            let compressed = self.read_bytes(block_info.compressed_size)?;
+
+            let mut decoder = AssetBundleDecoder::for_block(compressed, self.lzma_alone);
+            let started = Instant::now();
+            decoder.read_decompress(
+                block_info.compressed_size,
+                block_info.uncompressed_size,
+                block_info.flags.into(),
+            )?;
+            timings.push(BlockTiming {
+                index,
+                compression: Compression::from_flags(block_info.flags.into()),
+                compressed_size: block_info.compressed_size,
+                uncompressed_size: block_info.uncompressed_size,
+                elapsed: started.elapsed(),
+            });
+        }
+        Ok(timings)
+    }
+
+    /// Decompresses every block, checking each against its declared
+    /// uncompressed size, then checks that every directory entry lies within
+    /// the block data. Only fails when the metadata can't be read; problems
+    /// with blocks and entries are reported as failed checks.
+    pub fn validate(mut self) -> Result<Vec<ValidationCheck>> {
+        let bundle = self.read_metadata()?;
+        let mut checks = Vec::new();
+        for (index, block_info) in bundle.blocks_info.iter().enumerate() {
+            let compressed = match self.read_bytes(block_info.compressed_size) {
+                Ok(compressed) => compressed,
+                Err(err) => {
+                    // Nothing after a short read can be checked either
+                    checks.push(ValidationCheck::new(format!("Block {}", index), Err(err)));
+                    break;
+                }
+            };
+            let result = AssetBundleDecoder::for_block(compressed, self.lzma_alone)
+                .read_decompress(
+                    block_info.compressed_size,
+                    block_info.uncompressed_size,
+                    block_info.flags.into(),
+                )
+                .map(|_| ());
+            checks.push(ValidationCheck::new(format!("Block {}", index), result));
         }
 
+        let data_len: u64 = bundle
+            .blocks_info
+            .iter()
+            .map(|block| u64::from(block.uncompressed_size))
+            .sum();
+        for dir_info in &bundle.directory_info {
+            checks.push(ValidationCheck::new(
+                format!("Entry {}", dir_info.path),
+                check_entry_bounds(dir_info, data_len),
+            ));
+        }
+        Ok(checks)
+    }
+
+    /// Reads the header, block info and directory info, leaving the reader
+    /// at the start of the block data.
+    fn read_metadata(&mut self) -> Result<AssetBundle> {
+        let (signature, format) = self.read_signature()?;
+        if matches!(format, ArchiveFormat::UnityRaw | ArchiveFormat::UnityWeb) {
+            return self.read_raw_metadata(signature, format == ArchiveFormat::UnityWeb);
+        }
+
+        let Header {
+            signature,
+            version,
+            unity_version,
+            unity_revision,
+            size_offset: _,
+            size,
+            compressed_block_info_size,
+            uncompressed_block_info_size,
+            flags,
+        } = self.read_fs_header(signature)?;
+        // The block info of an encrypted bundle can't be read without its key
+        if is_encrypted(flags, &unity_revision) {
+            return Err(BundleError::UnsupportedBundle(format!(
+                "Encrypted {} bundle",
+                signature
+            )));
+        }
+
+        // Keep whatever sits between the known fields and the aligned end
+        // of the header verbatim, normally zero padding
+        let header_extra = if aligns_header(version) {
+            let position = self.inner.stream_position()? as usize;
+            let mut extra = vec![0u8; padding(position, 16)];
+            self.inner.read_exact(&mut extra)?;
+            extra
+        } else {
+            Vec::new()
+        };
+
+        let header_end = self.inner.stream_position()?;
         if flags & 0x80 != 0 {
             // kArchiveBlocksInfoAtTheEnd
             self.inner
@@ -112,34 +1105,44 @@ impl<R: Read + Seek> AssetBundleDecoder<R> {
             flags,
         )?;
 
+        if flags & 0x80 != 0 {
+            // Data still starts right after the header
+            self.inner.seek(SeekFrom::Start(header_end))?;
+        }
+
         let mut block_info_reader = Cursor::new(block_info_bytes);
 
-        // Skip hash
-        block_info_reader.seek(SeekFrom::Current(16))?;
+        let mut data_hash = [0u8; 16];
+        block_info_reader.read_exact(&mut data_hash)?;
 
         // Read blocks info
 
         let blocks_info_count = block_info_reader.read_u32()?;
+        check_entry_count(
+            "Block",
+            blocks_info_count,
+            BLOCK_INFO_LEN,
+            remaining_len(&block_info_reader),
+        )?;
         let mut blocks_info = Vec::with_capacity(blocks_info_count as usize);
         for _ in 0..blocks_info_count {
-            let uncompressed_size = block_info_reader.read_u32()?;
-            let compressed_size = block_info_reader.read_u32()?;
-            let flags = block_info_reader.read_u16()?;
-            blocks_info.push(BlockInfo {
-                uncompressed_size,
-                compressed_size,
-                flags,
-            });
+            blocks_info.push(BlockInfo::read_from(&mut block_info_reader)?);
         }
 
         // Read directory info
         let directory_info_count = block_info_reader.read_u32()?;
+        check_entry_count(
+            "Directory",
+            directory_info_count,
+            MIN_DIRECTORY_INFO_LEN,
+            remaining_len(&block_info_reader),
+        )?;
         let mut directory_info = Vec::with_capacity(directory_info_count as usize);
         for _ in 0..directory_info_count {
             let offset = block_info_reader.read_u64()?;
             let size = block_info_reader.read_u64()?;
             let flags = block_info_reader.read_u32()?;
-            let path = block_info_reader.read_string()?;
+            let path = block_info_reader.read_string_strict("Directory path")?;
             directory_info.push(DirectoryInfo {
                 offset,
                 size,
@@ -148,22 +1151,10 @@ impl<R: Read + Seek> AssetBundleDecoder<R> {
             });
         }
 
-        if flags & 0x200 != 0 {
+        if pads_block_info(flags, &unity_revision) {
             self.inner.align(16)?;
         }
 
-        if blocks_info.len() != 1 {
-            return Err(BundleError::MoreThanOneBlock);
-        }
-
-        let block_info = &blocks_info[0];
-
-        let block = self.read_decompress(
-            block_info.compressed_size,
-            block_info.uncompressed_size,
-            block_info.flags.into(),
-        )?;
-
         Ok(AssetBundle {
             signature,
             version,
@@ -173,100 +1164,843 @@ impl<R: Read + Seek> AssetBundleDecoder<R> {
             compressed_block_info_size,
             uncompressed_block_info_size,
             flags,
+            header_extra,
+            data_hash,
             blocks_info,
             directory_info,
-            block,
+            blocks: Vec::new(),
         })
     }
 
-    fn read_decompress(
-        &mut self,
-        compressed_size: u32,
-        uncompressed_size: u32,
-        flags: u32,
-    ) -> Result<Vec<u8>> {
-        let compression_type = flags & 0x3F;
+    /// Reads a UnityRaw header and the directory at the start of its data,
+    /// which becomes a single uncompressed block, or for UnityWeb a single
+    /// LZMA block. Directory offsets are relative to the start of that
+    /// data, as they are for UnityFS.
+    fn read_raw_metadata(&mut self, signature: String, compressed: bool) -> Result<AssetBundle> {
+        let version = self.inner.read_u32()?;
+        let unity_version = self.inner.read_string()?;
+        let unity_revision = self.inner.read_string()?;
+        if version >= 4 {
+            // Hash and CRC of the data
+            self.inner.seek(SeekFrom::Current(20))?;
+        }
+        let _minimum_streamed_bytes = self.inner.read_u32()?;
+        let header_size = self.inner.read_u32()?;
+        let _levels_before_streaming = self.inner.read_u32()?;
+
+        // Level sizes are cumulative, so the last covers all of the data
+        let level_count = self.inner.read_u32()?;
+        let mut data_sizes = None;
+        for _ in 0..level_count {
+            let compressed_size = self.inner.read_u32()?;
+            let uncompressed_size = self.inner.read_u32()?;
+            data_sizes = Some((compressed_size, uncompressed_size));
+        }
+        let (compressed_size, uncompressed_size) = data_sizes
+            .ok_or_else(|| BundleError::InvalidData("UnityRaw header lists no data".to_owned()))?;
+        let size = if version >= 2 {
+            self.inner.read_u32()?.into()
+        } else {
+            u64::from(header_size) + u64::from(compressed_size)
+        };
 
-        match compression_type {
+        let data_start = u64::from(header_size);
+        self.inner.seek(SeekFrom::Start(data_start))?;
+        self.lzma_alone = compressed;
+        let mut data: Box<dyn Read + '_> = if compressed {
+            Box::new(self.lzma_reader(compressed_size)?)
+        } else {
+            Box::new(&mut self.inner)
+        };
+        let directory_info_count = data.read_u32()?;
+        check_entry_count(
+            "Directory",
+            directory_info_count,
+            MIN_RAW_DIRECTORY_INFO_LEN,
+            uncompressed_size.into(),
+        )?;
+        let mut directory_info = Vec::new();
+        for _ in 0..directory_info_count {
+            let path = data.read_string_strict("Directory path")?;
+            let offset = data.read_u32()?.into();
+            let size = data.read_u32()?.into();
+            directory_info.push(DirectoryInfo {
+                offset,
+                size,
+                flags: 0,
+                path,
+            });
+        }
+        drop(data);
+        self.inner.seek(SeekFrom::Start(data_start))?;
+
+        Ok(AssetBundle {
+            signature,
+            version,
+            unity_version,
+            unity_revision,
+            size,
+            compressed_block_info_size: 0,
+            uncompressed_block_info_size: 0,
+            flags: 0,
+            header_extra: Vec::new(),
+            data_hash: [0; 16],
+            blocks_info: vec![BlockInfo {
+                uncompressed_size,
+                compressed_size,
+                flags: if compressed { 1 } else { 0 },
+            }],
+            directory_info,
+            blocks: Vec::new(),
+        })
+    }
+
+    /// Locates the alignment padding after the header and after the block
+    /// info and reports whether it is all zero.
+    pub fn padding_regions(mut self) -> Result<Vec<PaddingRegion>> {
+        let header = self.read_header()?;
+        let mut regions = Vec::new();
+
+        let mut position = self.inner.stream_position()?;
+        if aligns_header(header.version) {
+            let region = self.read_padding("header", position)?;
+            position += region.length;
+            regions.push(region);
+        }
+
+        if pads_block_info(header.flags, &header.unity_revision) {
+            // With the block info moved to the end the padding directly
+            // follows the header
+            if header.flags & 0x80 == 0 {
+                position += header.compressed_block_info_size as u64;
+                self.inner.seek(SeekFrom::Start(position))?;
+            }
+            regions.push(self.read_padding("blockInfo", position)?);
+        }
+
+        Ok(regions)
+    }
+
+    fn read_padding(&mut self, kind: &'static str, offset: u64) -> Result<PaddingRegion> {
+        let mut bytes = vec![0u8; padding(offset as usize, 16)];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(PaddingRegion {
+            kind,
+            offset,
+            length: bytes.len() as u64,
+            zeroed: bytes.iter().all(|byte| *byte == 0),
+        })
+    }
+
+    /// Reads only the header's declared total size and where it is stored.
+    pub fn read_size_field(mut self) -> Result<SizeField> {
+        let header = self.read_header()?;
+        Ok(SizeField {
+            offset: header.size_offset,
+            declared: header.size,
+        })
+    }
+
+    /// Reads the signature, failing for formats that can't be read at all.
+    fn read_signature(&mut self) -> Result<(String, ArchiveFormat)> {
+        let signature = self.inner.read_string_strict("Signature");
+        let signature = self.check_truncated(signature, "signature")?;
+        match ArchiveFormat::from_signature(&signature) {
+            Some(format) if format.can_decode() => Ok((signature, format)),
+            Some(format) => Err(BundleError::UnsupportedFormat(format.name())),
+            None => Err(BundleError::UnsupportedBundle(signature)),
+        }
+    }
+
+    /// Reads a UnityFS header, the only kind the header-level tools handle.
+    /// Legacy bundles have to be transcoded first.
+    fn read_header(&mut self) -> Result<Header> {
+        let (signature, format) = self.read_signature()?;
+        if format != ArchiveFormat::UnityFs {
+            return Err(BundleError::NeedsConversion(format.name()));
+        }
+        self.read_fs_header(signature)
+    }
+
+    fn read_fs_header(&mut self, signature: String) -> Result<Header> {
+        let header = self.read_fs_header_fields(signature);
+        self.check_truncated(header, "UnityFS header")
+    }
+
+    /// Replaces the bare "failed to fill whole buffer" of a file that ends
+    /// while reading `what`, typically left by a failed export, with where
+    /// it ended.
+    fn check_truncated<T>(&mut self, result: Result<T>, what: &str) -> Result<T> {
+        match result {
+            Err(BundleError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => Err(
+                BundleError::InvalidData(match self.inner.seek(SeekFrom::End(0)) {
+                    Ok(0) => "File is empty".to_owned(),
+                    Ok(len) => format!(
+                        "File is truncated at byte {}, before the end of the {}",
+                        len, what
+                    ),
+                    Err(_) => format!("File is truncated before the end of the {}", what),
+                }),
+            ),
+            result => result,
+        }
+    }
+
+    fn read_fs_header_fields(&mut self, signature: String) -> Result<Header> {
+        let version = self.inner.read_u32()?;
+        let unity_version = self.inner.read_string()?;
+        let unity_revision = self.inner.read_string()?;
+
+        let size_offset = self.inner.stream_position()?;
+        let size = self.inner.read_u64()?;
+        let compressed_block_info_size = self.inner.read_u32()?;
+        let uncompressed_block_info_size = self.inner.read_u32()?;
+        let flags = self.inner.read_u32()?;
+
+        Ok(Header {
+            signature,
+            version,
+            unity_version,
+            unity_revision,
+            size_offset,
+            size,
+            compressed_block_info_size,
+            uncompressed_block_info_size,
+            flags,
+        })
+    }
+
+    /// Reads exactly `len` bytes, growing the buffer only as data arrives.
+    fn read_bytes(&mut self, len: u32) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        (&mut self.inner)
+            .take(u64::from(len))
+            .read_to_end(&mut data)?;
+        if data.len() != len as usize {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(data)
+    }
+
+    fn read_decompress(
+        &mut self,
+        compressed_size: u32,
+        uncompressed_size: u32,
+        flags: u32,
+    ) -> Result<Vec<u8>> {
+        // No real block comes close, so larger sizes mean a malformed
+        // header rather than something worth allocating for
+        for (kind, size) in [
+            ("compressed", compressed_size),
+            ("uncompressed", uncompressed_size),
+        ] {
+            if size as usize > PC_UNCOMPRESSED_SIZE_LIMIT {
+                return Err(BundleError::InvalidData(format!(
+                    "Block declares {} {} bytes",
+                    size, kind
+                )));
+            }
+        }
+
+        let compression_type = flags & 0x3F;
+
+        // Every arm reads exactly `compressed_size` bytes at most, so the
+        // block info decodes the same whether it sits after the header or
+        // at the end of the file
+        let decompressed = match compression_type {
             1 => {
                 // LZMA
-                let mut header = [0u8; 5];
-                self.inner.read_exact(&mut header)?;
-                let stream = Stream::new_raw_decoder(Filters::new().lzma1_properties(&header)?)?;
-                let mut decoder = XzDecoder::new_stream(&mut self.inner, stream);
-
                 let mut decompressed = Vec::with_capacity(uncompressed_size as usize);
-                decoder.read_to_end(&mut decompressed)?;
-
-                Ok(decompressed)
+                self.lzma_reader(compressed_size)?
+                    .take(uncompressed_size as u64)
+                    .read_to_end(&mut decompressed)?;
+                decompressed
             }
             2 | 3 => {
                 // LZ4, LZ4HC
-                let mut data = Vec::with_capacity(compressed_size as usize);
-                unsafe {
-                    data.set_len(compressed_size as usize);
-                }
-                self.inner.read_exact(&mut data)?;
-                Ok(lz4_flex::decompress(&data, uncompressed_size as usize)?)
-            }
-            4 => Ok(zstd::decode_all(&mut self.inner)?),
-            _ => {
-                let mut data = Vec::with_capacity(compressed_size as usize);
-                unsafe {
-                    data.set_len(compressed_size as usize);
-                }
-                self.inner.read_exact(&mut data)?;
-                Ok(data)
+                let data = self.read_bytes(compressed_size)?;
+                let size = i32::try_from(uncompressed_size).map_err(|_| {
+                    BundleError::InvalidData(format!(
+                        "LZ4 block of {} bytes is too large",
+                        uncompressed_size
+                    ))
+                })?;
+                lz4::block::decompress(&data, Some(size)).map_err(BundleError::Decompress)?
             }
+            4 => zstd::decode_all((&mut self.inner).take(compressed_size as u64))?,
+            _ => self.read_bytes(compressed_size)?,
+        };
+
+        // A region read from the wrong place rarely decompresses to exactly
+        // the declared size
+        if decompressed.len() != uncompressed_size as usize {
+            return Err(BundleError::InvalidData(format!(
+                "Expected {} decompressed bytes, got {}",
+                uncompressed_size,
+                decompressed.len()
+            )));
         }
+        Ok(decompressed)
     }
+
+    /// Decompresses a block as it is read instead of all at once. LZ4 has
+    /// no streaming block format, so LZ4 blocks are still read whole.
+    fn block_reader(
+        &mut self,
+        compressed_size: u32,
+        uncompressed_size: u32,
+        flags: u32,
+    ) -> Result<Box<dyn Read + '_>> {
+        Ok(match flags & 0x3F {
+            1 => Box::new(
+                self.lzma_reader(compressed_size)?
+                    .take(uncompressed_size as u64),
+            ),
+            2 | 3 => Box::new(Cursor::new(self.read_decompress(
+                compressed_size,
+                uncompressed_size,
+                flags,
+            )?)),
+            4 => Box::new(zstd::stream::read::Decoder::new(
+                (&mut self.inner).take(compressed_size as u64),
+            )?),
+            _ => Box::new((&mut self.inner).take(compressed_size as u64)),
+        })
+    }
+
+    /// Decoder for a Unity LZMA block: the 5 property bytes, then raw LZMA
+    /// data without the uncompressed size of an `.lzma` header. UnityWeb
+    /// data is a whole `.lzma` stream, whose size field is skipped.
+    fn lzma_reader(&mut self, compressed_size: u32) -> Result<XzDecoder<io::Take<&mut R>>> {
+        let mut header = [0u8; 5];
+        self.inner.read_exact(&mut header)?;
+        check_lzma_properties(&header)?;
+        let mut header_len = 5;
+        if self.lzma_alone {
+            self.inner.seek(SeekFrom::Current(8))?;
+            header_len += 8;
+        }
+        let stream = Stream::new_raw_decoder(Filters::new().lzma1_properties(&header)?)?;
+        // The decoder buffers its input, so keep it from reading into the
+        // next block
+        let compressed =
+            (&mut self.inner).take(u64::from(compressed_size).saturating_sub(header_len));
+        Ok(XzDecoder::new_stream(compressed, stream))
+    }
+}
+
+impl AssetBundleDecoder<Cursor<Vec<u8>>> {
+    /// Decoder for one block's data already read into memory, reading LZMA
+    /// like the decoder it came from.
+    fn for_block(data: Vec<u8>, lzma_alone: bool) -> Self {
+        Self {
+            lzma_alone,
+            ..Self::new(Cursor::new(data))
+        }
+    }
+}
+
+/// Stage of `AssetBundleEncoder::transcode` reported to its progress
+/// callback, along with the bytes done and in total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum TranscodeStage {
+    /// Decompressing the input's blocks, counted in uncompressed bytes.
+    Decode,
+    /// Recompressing the decompressed data, counted in uncompressed bytes.
+    Compress,
+    /// Copying the recompressed blocks into the output, counted in
+    /// compressed bytes.
+    Write,
 }
 
-pub struct AssetBundleEncoder<W: Write + Seek> {
+type ProgressFn = Box<dyn FnMut(TranscodeStage, u64, u64) + Send>;
+
+/// Largest output block `transcode` holds in memory to compress alongside
+/// others. Larger blocks are compressed as they stream in, one at a time.
+const PARALLEL_BLOCK_MAX_LEN: u64 = 8 * 1024 * 1024;
+
+/// Bytes `transcode` handles at a time, and so between progress reports and
+/// cancellation checks.
+const CHUNK_LEN: usize = 1024 * 1024;
+
+/// How blocks are compressed, kept apart from the encoder's output and
+/// callbacks so the threads compressing blocks can share it.
+#[derive(Debug, Clone, Copy)]
+struct CompressionSettings {
+    reproducible: bool,
+    lzma_preset: u32,
+    lzma_dict_size: Option<u32>,
+    /// lc, lp and pb, each overriding the preset's value when set.
+    lzma_bits: [Option<u32>; 3],
+    zstd_level: i32,
+    lz4hc_level: i32,
+}
+
+pub struct AssetBundleEncoder<W: Write> {
     inner: W,
+    settings: CompressionSettings,
+    platform: Option<Platform>,
+    threads: usize,
+    block_size: Option<u32>,
+    progress: Option<ProgressFn>,
+    cancel: Option<CancellationToken>,
 }
 
-impl<W: Write + Seek> AssetBundleEncoder<W> {
+impl<W: Write> AssetBundleEncoder<W> {
     pub fn new(inner: W) -> Self {
-        Self { inner }
+        Self {
+            inner,
+            settings: CompressionSettings {
+                reproducible: false,
+                lzma_preset: 6,
+                lzma_dict_size: None,
+                lzma_bits: [None; 3],
+                zstd_level: zstd::DEFAULT_COMPRESSION_LEVEL,
+                lz4hc_level: LZ4HC_DEFAULT_LEVEL,
+            },
+            platform: None,
+            threads: default_threads(),
+            block_size: None,
+            progress: None,
+            cancel: None,
+        }
+    }
+
+    /// Stop with `BundleError::Cancelled` once `cancel` is cancelled,
+    /// checked between blocks and within them every megabyte. Nothing
+    /// more is written to the output after that.
+    pub fn cancel(mut self, cancel: Option<CancellationToken>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Called by `transcode` as it works through each `TranscodeStage`,
+    /// with the bytes done so far and the total for the stage.
+    pub fn progress(
+        mut self,
+        progress: impl FnMut(TranscodeStage, u64, u64) + Send + 'static,
+    ) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Cut the data into blocks of `block_size` uncompressed bytes, the
+    /// last one shorter, like Unity's 128 KiB LZ4 chunks, instead of
+    /// keeping the input's blocks. The new blocks all take the flags of
+    /// the input's first block. `None` or 0 keeps the input's blocks.
+    pub fn block_size(mut self, block_size: Option<u32>) -> Self {
+        self.block_size = block_size.filter(|&size| size > 0);
+        self
+    }
+
+    /// Most blocks `encode` compresses at once, at least 1. Defaults to the
+    /// number of CPUs, up to 4. Blocks are compressed independently, so
+    /// the output doesn't depend on it.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Fixed LZMA dictionary size instead of one sized to each block,
+    /// clamped to 512 KiB - 64 MiB. The size is stored in each block's
+    /// LZMA properties, so decoders need no other hint.
+    pub fn lzma_dict_size(mut self, dict_size: Option<u32>) -> Self {
+        self.settings.lzma_dict_size =
+            dict_size.map(|size| size.clamp(LZMA_MIN_DICT_SIZE, LZMA_MAX_DICT_SIZE));
+        self
+    }
+
+    /// LZMA literal context bits (lc), literal position bits (lp) and
+    /// position bits (pb), each left to the preset when `None`. lc + lp
+    /// may be at most 4 and pb at most 4, which encoding checks.
+    pub fn lzma_bits(mut self, lc: Option<u32>, lp: Option<u32>, pb: Option<u32>) -> Self {
+        self.settings.lzma_bits = [lc, lp, pb];
+        self
+    }
+
+    /// LZ4HC level for blocks flagged LZ4HC, clamped to 1-12. Plain LZ4
+    /// blocks keep the fast compressor.
+    pub fn lz4hc_level(mut self, level: i32) -> Self {
+        self.settings.lz4hc_level = level.clamp(1, LZ4HC_MAX_LEVEL);
+        self
+    }
+
+    /// ZSTD level, clamped to the range the library accepts.
+    pub fn zstd_level(mut self, level: i32) -> Self {
+        let levels = zstd::compression_level_range();
+        self.settings.zstd_level = level.clamp(*levels.start(), *levels.end());
+        self
+    }
+
+    /// LZMA preset from 0 (fastest) to 9 (smallest), clamped to that range.
+    /// The dictionary size is chosen separately, see `lzma_dict_size`.
+    pub fn lzma_preset(mut self, preset: u32) -> Self {
+        self.settings.lzma_preset = preset.min(9);
+        self
+    }
+
+    /// Refuse to write bundles over the size limits of `platform`. Nothing
+    /// is written when a limit is exceeded.
+    pub fn platform(mut self, platform: Option<Platform>) -> Self {
+        self.platform = platform;
+        self
+    }
+
+    /// Pin every compression parameter so identical input always produces
+    /// identical output bytes, independent of the linked codec library's
+    /// preset defaults. Blocks compressed on separate threads don't share
+    /// state, and no wall-clock data is written.
+    pub fn reproducible(mut self, reproducible: bool) -> Self {
+        self.settings.reproducible = reproducible;
+        self
     }
 
+    /// Writes `bundle`, converting one read from a legacy format to
+    /// UnityFS.
     pub fn encode(mut self, bundle: &AssetBundle) -> Result<()> {
-        if (bundle.blocks_info.len() != 1) {
-            return Err(BundleError::MoreThanOneBlock);
+        let mut metadata = bundle.metadata();
+        metadata.convert_to_unity_fs();
+        metadata.check_encodable()?;
+        if bundle.blocks.len() != bundle.blocks_info.len() {
+            return Err(BundleError::InvalidData(format!(
+                "{} blocks of data for {} blocks in block info",
+                bundle.blocks.len(),
+                bundle.blocks_info.len()
+            )));
         }
 
-        // Write header
-        self.inner.write_string(&bundle.signature)?;
-        self.inner.write_u32(bundle.version)?;
-        self.inner.write_string(&bundle.unity_version)?;
-        self.inner.write_string(&bundle.unity_revision)?;
+        if let Some(platform) = self.platform {
+            let uncompressed_size = bundle.blocks.iter().map(|block| block.len() as u64).sum();
+            check_size_limit(
+                "Uncompressed",
+                uncompressed_size,
+                platform.uncompressed_size_limit(),
+            )?;
+        }
 
-        // Placeholder for size
-        let size_pos = self.inner.stream_position()?;
-        self.inner.write_u64(0)?;
+        // Each block is recompressed on its own, keeping its boundaries so
+        // directory offsets into the concatenated data stay valid. Blocks
+        // cut to a fixed size are cut from that concatenation.
+        let joined;
+        let (blocks, flags): (Vec<&[u8]>, Vec<u16>) = match self.block_size {
+            Some(block_size) => {
+                joined = bundle.blocks.concat();
+                let flags = bundle.blocks_info.first().map_or(0, |block| block.flags);
+                joined
+                    .chunks(block_size as usize)
+                    .map(|data| (data, flags))
+                    .unzip()
+            }
+            None => bundle
+                .blocks
+                .iter()
+                .map(Vec::as_slice)
+                .zip(bundle.blocks_info.iter().map(|block| block.flags))
+                .unzip(),
+        };
+
+        let settings = &self.settings;
+        let mut compressed_blocks = Vec::with_capacity(blocks.len());
+        for (blocks, flags) in blocks.chunks(self.threads).zip(flags.chunks(self.threads)) {
+            self.check_cancelled()?;
+            let compressed = blocks
+                .par_iter()
+                .zip(flags)
+                .map(|(data, flags)| settings.compress(data, (flags & 0x3F).into()))
+                .collect::<Result<Vec<_>>>()?;
+            compressed_blocks.extend(compressed);
+        }
+        let blocks_info: Vec<_> = blocks
+            .iter()
+            .zip(flags)
+            .zip(&compressed_blocks)
+            .map(|((data, flags), compressed)| BlockInfo {
+                uncompressed_size: data.len() as u32,
+                compressed_size: compressed.len() as u32,
+                flags,
+            })
+            .collect();
+
+        let data_hash = blocks_hash(&bundle.blocks);
+        self.write_bundle(&metadata, &blocks_info, &data_hash, |inner| {
+            for compressed in &compressed_blocks {
+                inner.write_all(compressed)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Re-encodes the bundle read by `decoder` a few blocks at a time, so
+    /// neither the decompressed data nor the recompressed blocks are held
+    /// in memory whole. Blocks of up to 8 MiB are compressed `threads` at a
+    /// time; larger ones, like the single block of most LZMA bundles, are
+    /// compressed alone as they are read. Recompressed blocks are staged in
+    /// a temporary file until the block info that precedes them is known.
+    /// Blocks switch to `compression` when given, and the block info to
+    /// `block_info_compression`. The output matches decoding the bundle,
+    /// applying those settings and calling `encode`, so legacy bundles are
+    /// converted to UnityFS the same way.
+    pub fn transcode<R: Read + Seek>(
+        mut self,
+        mut decoder: AssetBundleDecoder<R>,
+        compression: Option<Compression>,
+        block_info_compression: Option<Compression>,
+    ) -> Result<()> {
+        let mut bundle = decoder.read_metadata()?;
+        bundle.convert_to_unity_fs();
+        bundle.check_encodable()?;
+        if let Some(compression) = block_info_compression {
+            bundle.set_block_info_compression(compression);
+        }
+
+        let uncompressed_size: u64 = bundle
+            .blocks_info
+            .iter()
+            .map(|block| u64::from(block.uncompressed_size))
+            .sum();
+        if let Some(platform) = self.platform {
+            check_size_limit(
+                "Uncompressed",
+                uncompressed_size,
+                platform.uncompressed_size_limit(),
+            )?;
+        }
+
+        let block_size = self.block_size.map(u64::from);
+        let spool = tempfile::tempfile()?;
+        let mut blocks_info = Vec::with_capacity(bundle.blocks_info.len());
+        let mut open = None;
+        // Small blocks are collected and compressed `threads` at a time
+        let mut pending = Vec::new();
+        let mut offset = decoder.inner.stream_position()?;
+        let mut transcoded = 0;
+        let mut compressed = 0;
+        let mut data_hash = Md5::new();
+        for block_info in &bundle.blocks_info {
+            // Decoders may stop short of the end of their input, so each
+            // block is located from the block info rather than from where
+            // the previous one left off
+            decoder.inner.seek(SeekFrom::Start(offset))?;
+            offset += u64::from(block_info.compressed_size);
+
+            let flags = match (block_size, bundle.blocks_info.first()) {
+                (Some(_), Some(first)) => first.flags,
+                _ => block_info.flags,
+            };
+            let flags = match compression {
+                Some(compression) => (flags & !0x3F) | compression.flags() as u16,
+                None => flags,
+            };
+            let mut reader = decoder.block_reader(
+                block_info.compressed_size,
+                block_info.uncompressed_size,
+                block_info.flags.into(),
+            )?;
+
+            // Without a block size each input block becomes one output
+            // block, otherwise output blocks run across input blocks
+            let mut remaining = u64::from(block_info.uncompressed_size);
+            loop {
+                let block = match &mut open {
+                    Some(block) => block,
+                    None => {
+                        let len = block_size.unwrap_or(remaining);
+                        let sink = if self.threads > 1 && len <= PARALLEL_BLOCK_MAX_LEN {
+                            BlockSink::Buffered(Vec::with_capacity(len as usize))
+                        } else {
+                            // Blocks reach the spool in order
+                            compressed +=
+                                self.compress_pending(&mut pending, &spool, &mut blocks_info)?;
+                            BlockSink::Streamed(self.settings.block_writer(
+                                CountingWriter::new(BufWriter::new(&spool)),
+                                (flags & 0x3F).into(),
+                                len as usize,
+                            )?)
+                        };
+                        open.insert(OpenBlock {
+                            sink,
+                            uncompressed_size: 0,
+                            flags,
+                        })
+                    }
+                };
+                let room = block_size.map_or(remaining, |size| {
+                    remaining.min(size - block.uncompressed_size)
+                });
+                // In pieces, so a large block still reports progress and
+                // can be cancelled part way
+                let piece = room.min(CHUNK_LEN as u64);
+                let copied = io::copy(
+                    &mut (&mut reader).take(piece),
+                    &mut HashingWriter {
+                        inner: &mut block.sink,
+                        hasher: &mut data_hash,
+                    },
+                )?;
+                block.uncompressed_size += copied;
+                remaining -= copied;
+                transcoded += copied;
+                // Streamed blocks are compressed as they are decoded,
+                // buffered ones once the queue is compressed
+                if matches!(block.sink, BlockSink::Streamed(_)) {
+                    compressed += copied;
+                }
+                self.report(TranscodeStage::Decode, transcoded, uncompressed_size);
+                self.check_cancelled()?;
+                if copied < piece {
+                    return Err(BundleError::InvalidData(format!(
+                        "Block decompressed to {} bytes instead of {}",
+                        u64::from(block_info.uncompressed_size) - remaining,
+                        block_info.uncompressed_size
+                    )));
+                }
 
-        let compressed_block =
-            self.compress(&bundle.block, (bundle.blocks_info[0].flags & 0x3F).into())?;
+                let full = match block_size {
+                    Some(size) => block.uncompressed_size == size,
+                    None => remaining == 0,
+                };
+                if full {
+                    if let Some(block) = open.take() {
+                        compressed +=
+                            self.close_block(block, &mut pending, &spool, &mut blocks_info)?;
+                    }
+                }
+                self.report(TranscodeStage::Compress, compressed, uncompressed_size);
+                if remaining == 0 {
+                    break;
+                }
+            }
+        }
+        if let Some(block) = open.take() {
+            compressed += self.close_block(block, &mut pending, &spool, &mut blocks_info)?;
+        }
+        compressed += self.compress_pending(&mut pending, &spool, &mut blocks_info)?;
+        self.report(TranscodeStage::Compress, compressed, uncompressed_size);
+        let data_hash = data_hash.finalize().into();
+
+        // Through a shared reference, like the block writers
+        let mut spool = &spool;
+        let spool_len = spool.seek(SeekFrom::End(0))?;
+        spool.seek(SeekFrom::Start(0))?;
+        let mut progress = self.progress.take();
+        let cancel = self.cancel.clone();
+        self.write_bundle(&bundle, &blocks_info, &data_hash, |inner| {
+            let mut buf = vec![0u8; CHUNK_LEN];
+            let mut written = 0;
+            loop {
+                if cancel.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                    return Err(BundleError::Cancelled);
+                }
+                let len = spool.read(&mut buf)?;
+                if len == 0 {
+                    return Ok(());
+                }
+                inner.write_all(&buf[..len])?;
+                written += len as u64;
+                if let Some(progress) = &mut progress {
+                    progress(TranscodeStage::Write, written, spool_len);
+                }
+            }
+        })
+    }
+
+    /// Finishes a streamed block, or queues a buffered one and compresses
+    /// the queue once there is a block for every thread. Returns the
+    /// uncompressed bytes of the queue if it was compressed.
+    fn close_block(
+        &self,
+        block: OpenBlock<BufWriter<&File>>,
+        pending: &mut Vec<(Vec<u8>, u16)>,
+        spool: &File,
+        blocks_info: &mut Vec<BlockInfo>,
+    ) -> Result<u64> {
+        match block.sink {
+            BlockSink::Streamed(writer) => {
+                let written = writer.finish()?.written;
+                blocks_info.push(BlockInfo {
+                    // Never beyond the input block or block size it was cut to
+                    uncompressed_size: block.uncompressed_size as u32,
+                    compressed_size: compressed_block_size(written)?,
+                    flags: block.flags,
+                });
+            }
+            BlockSink::Buffered(data) => {
+                pending.push((data, block.flags));
+                if pending.len() >= self.threads {
+                    return self.compress_pending(pending, spool, blocks_info);
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    /// Compresses the queued blocks concurrently and appends them to the
+    /// spool in order, returning their uncompressed size.
+    fn compress_pending(
+        &self,
+        pending: &mut Vec<(Vec<u8>, u16)>,
+        mut spool: &File,
+        blocks_info: &mut Vec<BlockInfo>,
+    ) -> Result<u64> {
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        self.check_cancelled()?;
+        let settings = &self.settings;
+        let compressed = pending
+            .par_iter()
+            .map(|(data, flags)| settings.compress(data, (flags & 0x3F).into()))
+            .collect::<Result<Vec<_>>>()?;
+        let len = pending.iter().map(|(data, _)| data.len() as u64).sum();
+        for ((data, flags), compressed) in pending.drain(..).zip(compressed) {
+            spool.write_all(&compressed)?;
+            blocks_info.push(BlockInfo {
+                uncompressed_size: data.len() as u32,
+                compressed_size: compressed_block_size(compressed.len() as u64)?,
+                flags,
+            });
+        }
+        Ok(len)
+    }
 
+    /// Writes the header, the block info for `blocks_info` and, through
+    /// `write_data`, the compressed blocks in the order the layout calls
+    /// for. Everything but the block data and `data_hash`, the hash of the
+    /// uncompressed data, comes from `bundle`.
+    fn write_bundle(
+        &mut self,
+        bundle: &AssetBundle,
+        blocks_info: &[BlockInfo],
+        data_hash: &[u8; 16],
+        write_data: impl FnOnce(&mut W) -> Result<()>,
+    ) -> Result<()> {
         // Create and compress block info
         let block_info = {
             let mut writer = Cursor::new(Vec::new());
 
-            // Placeholder for hash (16 bytes of zeros)
-            writer.write_all(&[0u8; 16])?;
+            // The field AssetStudio's `BundleFile` reads as
+            // `uncompressedDataHash`. Unity doesn't document its algorithm
+            // and nothing checks it on load, the decoder included, so an
+            // MD5 of the uncompressed block data fills it.
+            writer.write_all(data_hash)?;
 
             // Write blocks info
-            writer.write_u32(1)?; // Only one block
-            writer.write_u32(bundle.block.len() as u32)?;
-            writer.write_u32(compressed_block.len() as u32)?;
-            writer.write_all(&(bundle.blocks_info[0].flags).to_be_bytes())?;
+            writer.write_u32(blocks_info.len() as u32)?;
+            for block_info in blocks_info {
+                block_info.write_to(&mut writer)?;
+            }
 
             // Write directory info
             writer.write_u32(bundle.directory_info.len() as u32)?;
 
-            // Assumes files didn't change in size
+            // Entries are resized and moved by `AssetBundle::replace_entry`
+            let data_len: u64 = blocks_info
+                .iter()
+                .map(|block| u64::from(block.uncompressed_size))
+                .sum();
             for dir_info in &bundle.directory_info {
+                check_entry_bounds(dir_info, data_len)?;
                 writer.write_u64(dir_info.offset)?;
                 writer.write_u64(dir_info.size)?;
                 writer.write_u32(dir_info.flags)?;
@@ -275,30 +2009,74 @@ impl<W: Write + Seek> AssetBundleEncoder<W> {
 
             writer.into_inner()
         };
-        let compressed_block_info = self.compress(&block_info, bundle.flags & 0x3F)?;
+        let compressed_block_info = self.settings.compress(&block_info, bundle.flags & 0x3F)?;
 
-        self.inner.write_u32(compressed_block_info.len() as u32)?;
-        self.inner.write_u32(block_info.len() as u32)?;
-        self.inner.write_u32(bundle.flags)?;
+        // Build the header up front so the total size is known before
+        // anything is written and the output never has to be seeked.
+        let mut header = Cursor::new(Vec::new());
+        header.write_string(&bundle.signature)?;
+        header.write_u32(bundle.version)?;
+        header.write_string(&bundle.unity_version)?;
+        header.write_string(&bundle.unity_revision)?;
 
-        // Alignment
-        if bundle.version >= 7 {
-            self.inner.align(16)?;
-        }
-
-        // Write block info and data
-        self.inner.write_all(&compressed_block_info)?;
+        // Placeholder for size
+        let size_pos = header.position();
+        header.write_u64(0)?;
+
+        header.write_u32(compressed_block_info.len() as u32)?;
+        header.write_u32(block_info.len() as u32)?;
+        header.write_u32(bundle.flags)?;
+
+        // Alignment, reusing the original padding bytes where they fit
+        let mut header_padding = if aligns_header(bundle.version) {
+            vec![0u8; padding(header.get_ref().len(), 16)]
+        } else {
+            Vec::new()
+        };
+        let extra_len = bundle.header_extra.len().min(header_padding.len());
+        header_padding[..extra_len].copy_from_slice(&bundle.header_extra[..extra_len]);
+
+        // kArchiveBlocksInfoAtTheEnd moves the block info after the data,
+        // which then starts right after the header
+        let info_at_end = bundle.flags & 0x80 != 0;
+        let header_end = header.get_ref().len() + header_padding.len();
+        let data_start = if info_at_end {
+            header_end
+        } else {
+            header_end + compressed_block_info.len()
+        };
+        let block_info_padding = if pads_block_info(bundle.flags, &bundle.unity_revision) {
+            padding(data_start, 16)
+        } else {
+            0
+        };
 
-        if bundle.flags & 0x200 != 0 {
-            self.inner.align(16)?;
+        // Final size
+        let blocks_len: u64 = blocks_info
+            .iter()
+            .map(|block| u64::from(block.compressed_size))
+            .sum();
+        let size =
+            (header_end + compressed_block_info.len() + block_info_padding) as u64 + blocks_len;
+        header.set_position(size_pos);
+        header.write_u64(size)?;
+        let header = header.into_inner();
+
+        if let Some(platform) = self.platform {
+            check_size_limit("Compressed", size, platform.compressed_size_limit())?;
         }
 
-        self.inner.write_all(&compressed_block)?;
-
-        // Write final size
-        let end_pos = self.inner.stream_position()?;
-        self.inner.seek(SeekFrom::Start(size_pos))?;
-        self.inner.write_u64(end_pos)?;
+        // Write header, block info and data
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&header_padding)?;
+        if !info_at_end {
+            self.inner.write_all(&compressed_block_info)?;
+        }
+        self.inner.write_all(&vec![0u8; block_info_padding])?;
+        write_data(&mut self.inner)?;
+        if info_at_end {
+            self.inner.write_all(&compressed_block_info)?;
+        }
 
         // Write to file
         self.inner.flush()?;
@@ -306,39 +2084,289 @@ impl<W: Write + Seek> AssetBundleEncoder<W> {
         Ok(())
     }
 
-    fn compress(&mut self, data: &[u8], compression_type: u32) -> Result<Vec<u8>> {
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel {
+            Some(cancel) if cancel.is_cancelled() => Err(BundleError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn report(&mut self, stage: TranscodeStage, done: u64, total: u64) {
+        if let Some(progress) = &mut self.progress {
+            progress(stage, done, total);
+        }
+    }
+}
+
+impl CompressionSettings {
+    fn compress(&self, data: &[u8], compression_type: u32) -> Result<Vec<u8>> {
+        let mut compressed = Vec::new();
+        let mut writer = self.block_writer(&mut compressed, compression_type, data.len())?;
+        writer.write_all(data)?;
+        writer.finish()?;
+        Ok(compressed)
+    }
+
+    /// Starts compressing a block of `len` bytes into `inner`, in the form
+    /// Unity stores it.
+    fn block_writer<O: Write>(
+        &self,
+        inner: O,
+        compression_type: u32,
+        len: usize,
+    ) -> Result<BlockWriter<O>> {
         match compression_type {
             1 => {
-                let mut options = stream::LzmaOptions::new_preset(6)?;
-                options.dict_size(524288); // Unity dict size
-                                           // .literal_context_bits(3)
-                                           // .position_bits(2)
-                                           // .literal_position_bits(0);
-                let stream = Stream::new_lzma_encoder(&options)?;
-                let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
-
-                // Compress data
-                encoder.write_all(data)?;
-                let compressed = encoder.finish()?;
-
-                let mut compressed_unity_format = Vec::new();
-                compressed_unity_format.extend_from_slice(&compressed[..5]); // append props and dict size
-                                                                             // skipping uncompressed size field (unity includes it in block info)
-                compressed_unity_format.extend_from_slice(&compressed[13..]); // append compressed data
-
-                Ok(compressed_unity_format)
+                let stream = Stream::new_lzma_encoder(&self.lzma_options(len)?)?;
+                Ok(BlockWriter::Lzma(XzEncoder::new_stream(
+                    LzmaHeaderWriter { inner, position: 0 },
+                    stream,
+                )))
             }
-            2 | 3 => {
-                // LZ4, LZ4HC
-                Ok(lz4_flex::compress(data))
+            2 => Ok(BlockWriter::Lz4(Vec::with_capacity(len), None, inner)),
+            3 => Ok(BlockWriter::Lz4(
+                Vec::with_capacity(len),
+                Some(self.lz4hc_level),
+                inner,
+            )),
+            4 => Ok(BlockWriter::Zstd(zstd::stream::write::Encoder::new(
+                inner,
+                self.zstd_level,
+            )?)),
+            0 => Ok(BlockWriter::Store(inner)),
+            _ => Err(BundleError::UnsupportedBundle(format!(
+                "Unknown compression type: {}",
+                compression_type
+            ))),
+        }
+    }
+
+    fn lzma_options(&self, len: usize) -> Result<stream::LzmaOptions> {
+        let preset = self.lzma_preset;
+        let mut options = stream::LzmaOptions::new_preset(preset)?;
+        // A dictionary beyond the data's size gains nothing
+        let dict_size = self.lzma_dict_size.unwrap_or_else(|| {
+            (len.max(1) as u64)
+                .next_power_of_two()
+                .clamp(LZMA_MIN_DICT_SIZE.into(), LZMA_MAX_DICT_SIZE.into()) as u32
+        });
+        options.dict_size(dict_size);
+        if self.reproducible {
+            // Values of the preset, spelled out so they can't drift
+            let (mode, match_finder, nice_len, depth) = match preset {
+                0 => (stream::Mode::Fast, stream::MatchFinder::HashChain3, 128, 4),
+                1 => (stream::Mode::Fast, stream::MatchFinder::HashChain4, 128, 8),
+                2 => (stream::Mode::Fast, stream::MatchFinder::HashChain4, 273, 24),
+                3 => (stream::Mode::Fast, stream::MatchFinder::HashChain4, 273, 48),
+                4 => (
+                    stream::Mode::Normal,
+                    stream::MatchFinder::BinaryTree4,
+                    16,
+                    0,
+                ),
+                5 => (
+                    stream::Mode::Normal,
+                    stream::MatchFinder::BinaryTree4,
+                    32,
+                    0,
+                ),
+                _ => (
+                    stream::Mode::Normal,
+                    stream::MatchFinder::BinaryTree4,
+                    64,
+                    0,
+                ),
+            };
+            options
+                .literal_context_bits(3)
+                .literal_position_bits(0)
+                .position_bits(2)
+                .mode(mode)
+                .nice_len(nice_len)
+                .match_finder(match_finder)
+                .depth(depth);
+        }
+
+        let [lc, lp, pb] = self.lzma_bits;
+        let (lc_value, lp_value) = (lc.unwrap_or(3), lp.unwrap_or(0));
+        if lc_value + lp_value > 4 || pb.is_some_and(|pb| pb > 4) {
+            return Err(BundleError::InvalidData(format!(
+                "LZMA lc={} lp={} pb={} is out of range, lc + lp and pb must be at most 4",
+                lc_value,
+                lp_value,
+                pb.unwrap_or(2)
+            )));
+        }
+        if let Some(lc) = lc {
+            options.literal_context_bits(lc);
+        }
+        if let Some(lp) = lp {
+            options.literal_position_bits(lp);
+        }
+        if let Some(pb) = pb {
+            options.position_bits(pb);
+        }
+        Ok(options)
+    }
+}
+
+/// A block being compressed, finished by `finish`.
+enum BlockWriter<W: Write> {
+    Lzma(XzEncoder<LzmaHeaderWriter<W>>),
+    /// LZ4 compresses whole blocks, so the data is collected first. With
+    /// a level it is compressed as LZ4HC.
+    Lz4(Vec<u8>, Option<i32>, W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Store(W),
+}
+
+impl<W: Write> BlockWriter<W> {
+    /// Writes what is still buffered and hands back the output.
+    fn finish(self) -> Result<W> {
+        let mut inner = match self {
+            BlockWriter::Lzma(encoder) => encoder.finish()?.inner,
+            BlockWriter::Lz4(data, level, mut inner) => {
+                let mode = level.map(lz4::block::CompressionMode::HIGHCOMPRESSION);
+                let compressed = lz4::block::compress(&data, mode, false)?;
+                inner.write_all(&compressed)?;
+                inner
             }
-            _ => Ok(data.to_vec()),
+            BlockWriter::Zstd(encoder) => encoder.finish()?,
+            BlockWriter::Store(inner) => inner,
+        };
+        inner.flush()?;
+        Ok(inner)
+    }
+}
+
+/// An output block of `transcode` still being filled.
+struct OpenBlock<W: Write> {
+    sink: BlockSink<W>,
+    uncompressed_size: u64,
+    flags: u16,
+}
+
+/// Where `transcode` puts an output block's data: compressed straight into
+/// the spool, or kept uncompressed to be compressed alongside other blocks.
+enum BlockSink<W: Write> {
+    Streamed(BlockWriter<CountingWriter<W>>),
+    Buffered(Vec<u8>),
+}
+
+impl<W: Write> Write for BlockSink<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BlockSink::Streamed(writer) => writer.write(buf),
+            BlockSink::Buffered(data) => data.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BlockSink::Streamed(writer) => writer.flush(),
+            BlockSink::Buffered(_) => Ok(()),
+        }
+    }
+}
+
+fn compressed_block_size(len: u64) -> Result<u32> {
+    u32::try_from(len)
+        .map_err(|_| BundleError::InvalidData("Recompressed block exceeds 4 GiB".to_owned()))
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            BlockWriter::Lzma(encoder) => encoder.write(buf),
+            BlockWriter::Lz4(data, _, _) => data.write(buf),
+            BlockWriter::Zstd(encoder) => encoder.write(buf),
+            BlockWriter::Store(inner) => inner.write(buf),
         }
     }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            BlockWriter::Lzma(encoder) => encoder.flush(),
+            BlockWriter::Lz4(..) => Ok(()),
+            BlockWriter::Zstd(encoder) => encoder.flush(),
+            BlockWriter::Store(inner) => inner.flush(),
+        }
+    }
+}
+
+/// Drops the 8-byte uncompressed size that follows the 5 property bytes of
+/// an `.lzma` stream, since Unity keeps it in the block info instead.
+struct LzmaHeaderWriter<W: Write> {
+    inner: W,
+    position: u64,
+}
+
+impl<W: Write> Write for LzmaHeaderWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = match self.position {
+            0..=4 => self
+                .inner
+                .write(&buf[..buf.len().min(5 - self.position as usize)])?,
+            5..=12 => buf.len().min(13 - self.position as usize),
+            _ => self.inner.write(buf)?,
+        };
+        self.position += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Counts the bytes written through it.
+pub struct CountingWriter<W: Write> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner, written: 0 }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Feeds the bytes written through it to `hasher`.
+struct HashingWriter<'a, W: Write> {
+    inner: W,
+    hasher: &'a mut Md5,
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
 }
 
 trait ReadExt: Read {
-    fn read_string(&mut self) -> io::Result<String> {
+    fn read_cstring(&mut self) -> io::Result<Vec<u8>> {
         let mut result = Vec::new();
         loop {
             let mut buf = [0u8; 1];
@@ -348,7 +2376,19 @@ trait ReadExt: Read {
             }
             result.push(buf[0]);
         }
-        Ok(String::from_utf8_lossy(&result).into_owned())
+        Ok(result)
+    }
+
+    /// Replaces invalid UTF-8, for informational fields only.
+    fn read_string(&mut self) -> io::Result<String> {
+        Ok(String::from_utf8_lossy(&self.read_cstring()?).into_owned())
+    }
+
+    /// For fields that identify something or get written back, where a
+    /// lossy conversion would silently change the value.
+    fn read_string_strict(&mut self, field: &str) -> Result<String> {
+        String::from_utf8(self.read_cstring()?)
+            .map_err(|_| BundleError::InvalidData(format!("{} is not valid UTF-8", field)))
     }
 
     fn read_u16(&mut self) -> io::Result<u16> {
@@ -405,13 +2445,834 @@ trait WriteExt: Write {
 
 impl<W: Write> WriteExt for W {}
 
-trait AlignWriteExt: Write + Seek {
-    fn align(&mut self, alignment: u64) -> io::Result<()> {
-        let current_position = self.stream_position()?;
-        let aligned_position = (current_position + alignment - 1) & !(alignment - 1);
-        self.seek(SeekFrom::Start(aligned_position))?;
-        Ok(())
-    }
+/// Number of zero bytes needed to pad `position` up to `alignment`.
+fn padding(position: usize, alignment: usize) -> usize {
+    (alignment - position % alignment) % alignment
 }
 
-impl<W: Write + Seek> AlignWriteExt for W {}
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Compressible data that isn't one repeated byte.
+    pub(crate) fn sample_data(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i / 7 % 251) as u8 ^ (i % 13) as u8)
+            .collect()
+    }
+
+    /// A 2022.3 UnityFS bundle with a serialized file and its resources in
+    /// `block_count` blocks compressed with `compression`.
+    pub(crate) fn sample_bundle(compression: Compression, block_count: usize) -> AssetBundle {
+        let data = sample_data(300 * 1024);
+        let blocks: Vec<_> = data
+            .chunks(data.len().div_ceil(block_count))
+            .map(<[u8]>::to_vec)
+            .collect();
+        AssetBundle {
+            signature: "UnityFS".to_owned(),
+            version: 8,
+            unity_version: "5.x.x".to_owned(),
+            unity_revision: "2022.3.22f1".to_owned(),
+            size: 0,
+            compressed_block_info_size: 0,
+            uncompressed_block_info_size: 0,
+            flags: 0x200 | 0x40 | compression.flags(),
+            header_extra: Vec::new(),
+            data_hash: [0; 16],
+            blocks_info: blocks
+                .iter()
+                .map(|block| BlockInfo {
+                    uncompressed_size: block.len() as u32,
+                    compressed_size: 0,
+                    flags: compression.flags() as u16,
+                })
+                .collect(),
+            directory_info: vec![
+                DirectoryInfo {
+                    offset: 0,
+                    size: 100 * 1024,
+                    flags: 4,
+                    path: "CAB-sample".to_owned(),
+                },
+                DirectoryInfo {
+                    offset: 100 * 1024,
+                    size: 200 * 1024,
+                    flags: 0,
+                    path: "CAB-sample.resS".to_owned(),
+                },
+            ],
+            blocks,
+        }
+    }
+
+    pub(crate) type VecEncoder<'a> = AssetBundleEncoder<&'a mut Vec<u8>>;
+
+    pub(crate) fn encode_with(
+        bundle: &AssetBundle,
+        configure: impl FnOnce(VecEncoder) -> VecEncoder,
+    ) -> Vec<u8> {
+        let mut output = Vec::new();
+        configure(AssetBundleEncoder::new(&mut output))
+            .encode(bundle)
+            .unwrap();
+        output
+    }
+
+    pub(crate) fn transcode_with(
+        input: &[u8],
+        compression: Option<Compression>,
+        configure: impl FnOnce(VecEncoder) -> VecEncoder,
+    ) -> Vec<u8> {
+        let mut output = Vec::new();
+        configure(AssetBundleEncoder::new(&mut output))
+            .transcode(
+                AssetBundleDecoder::new(Cursor::new(input.to_vec())),
+                compression,
+                None,
+            )
+            .unwrap();
+        output
+    }
+
+    /// A version 3 UnityRaw bundle holding `entries`, or UnityWeb with its
+    /// data in an `.lzma` stream.
+    pub(crate) fn legacy_bundle(web: bool, entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let table_len: usize = 4 + entries
+            .iter()
+            .map(|(path, _)| path.len() + 9)
+            .sum::<usize>();
+        let mut data = Vec::new();
+        data.write_u32(entries.len() as u32).unwrap();
+        let mut offset = table_len;
+        for (path, contents) in entries {
+            data.write_string(path).unwrap();
+            data.write_u32(offset as u32).unwrap();
+            data.write_u32(contents.len() as u32).unwrap();
+            offset += contents.len();
+        }
+        for (_, contents) in entries {
+            data.extend_from_slice(contents);
+        }
+        let uncompressed_len = data.len() as u32;
+        if web {
+            let options = stream::LzmaOptions::new_preset(6).unwrap();
+            let stream = Stream::new_lzma_encoder(&options).unwrap();
+            let mut encoder = XzEncoder::new_stream(Vec::new(), stream);
+            encoder.write_all(&data).unwrap();
+            data = encoder.finish().unwrap();
+        }
+
+        let mut header = Vec::new();
+        header
+            .write_string(if web { "UnityWeb" } else { "UnityRaw" })
+            .unwrap();
+        header.write_u32(3).unwrap();
+        header.write_string("3.x.x").unwrap();
+        header.write_string("5.2.0f3").unwrap();
+        // Minimum streamed bytes, header size, levels before streaming
+        // and a single level
+        let header_size = header.len() as u32 + 32;
+        for value in [0, header_size, 1, 1, data.len() as u32, uncompressed_len] {
+            header.write_u32(value).unwrap();
+        }
+        header.write_u32(header_size + data.len() as u32).unwrap();
+        header.resize(header_size as usize, 0);
+        header.extend(data);
+        header
+    }
+
+    fn decode(bytes: &[u8]) -> AssetBundle {
+        AssetBundleDecoder::new(Cursor::new(bytes.to_vec()))
+            .decode()
+            .unwrap()
+    }
+
+    #[test]
+    fn parallel_compression_matches_sequential() {
+        for compression in [Compression::Lzma, Compression::Lz4Hc, Compression::Zstd] {
+            let bundle = sample_bundle(compression, 5);
+            let sequential = encode_with(&bundle, |encoder| encoder.threads(1));
+            let parallel = encode_with(&bundle, |encoder| encoder.threads(4));
+            assert_eq!(sequential, parallel, "{compression:?} encode");
+
+            // Transcoding buffers blocks for the threads but streams them
+            // through a single one
+            let block_size = Some(64 * 1024);
+            let sequential = transcode_with(&parallel, None, |encoder| {
+                encoder.threads(1).block_size(block_size)
+            });
+            let parallel = transcode_with(&parallel, None, |encoder| {
+                encoder.threads(4).block_size(block_size)
+            });
+            assert_eq!(sequential, parallel, "{compression:?} transcode");
+        }
+    }
+
+    #[test]
+    fn reproducible_encoding_is_byte_identical() {
+        for compression in Compression::ALL {
+            let bundle = sample_bundle(compression, 3);
+            let first = encode_with(&bundle, |encoder| encoder.reproducible(true));
+            let second = encode_with(&bundle, |encoder| encoder.reproducible(true));
+            assert_eq!(first, second, "{compression:?} encode");
+
+            let first = transcode_with(&first, None, |encoder| encoder.reproducible(true));
+            let second = transcode_with(&second, None, |encoder| encoder.reproducible(true));
+            assert_eq!(first, second, "{compression:?} transcode");
+        }
+    }
+
+    #[test]
+    fn two_block_bundle_round_trips() {
+        let bundle = sample_bundle(Compression::Lz4Hc, 2);
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.blocks.len(), 2);
+        assert_eq!(decoded.blocks, bundle.blocks);
+        assert!(bundle.first_difference(&decoded).is_none());
+        // The resource entry starts in the first block and ends in the second
+        let data = bundle.blocks.concat();
+        for entry in decoded.directory_info() {
+            let range = entry.offset as usize..(entry.offset + entry.size) as usize;
+            assert_eq!(decoded.entry_data(entry).unwrap().as_ref(), &data[range]);
+        }
+
+        assert_eq!(encode_with(&decoded, |encoder| encoder), encoded);
+
+        let mut missing_block = decoded;
+        missing_block.blocks.pop();
+        let result = AssetBundleEncoder::new(Vec::new()).encode(&missing_block);
+        assert!(matches!(result, Err(BundleError::InvalidData(_))));
+    }
+
+    /// A UnityFS bundle with one stored block of `len` zero bytes, whose
+    /// data is produced as it is read instead of held in memory.
+    struct ZeroBundle {
+        header: Vec<u8>,
+        len: u64,
+        position: u64,
+    }
+
+    impl ZeroBundle {
+        fn new(len: u32) -> Self {
+            let mut block_info = vec![0; 16];
+            block_info.write_u32(1).unwrap();
+            BlockInfo {
+                uncompressed_size: len,
+                compressed_size: len,
+                flags: 0,
+            }
+            .write_to(&mut block_info)
+            .unwrap();
+            block_info.write_u32(1).unwrap();
+            block_info.write_u64(0).unwrap();
+            block_info.write_u64(len.into()).unwrap();
+            block_info.write_u32(4).unwrap();
+            block_info.write_string("CAB-zeros").unwrap();
+
+            let mut header = Vec::new();
+            header.write_string("UnityFS").unwrap();
+            header.write_u32(6).unwrap();
+            header.write_string("5.x.x").unwrap();
+            header.write_string("2019.4.31f1").unwrap();
+            let size = header.len() as u64 + 20 + block_info.len() as u64 + u64::from(len);
+            header.write_u64(size).unwrap();
+            header.write_u32(block_info.len() as u32).unwrap();
+            header.write_u32(block_info.len() as u32).unwrap();
+            header.write_u32(0x40).unwrap();
+            header.extend(block_info);
+            Self {
+                len: size,
+                header,
+                position: 0,
+            }
+        }
+    }
+
+    impl Read for ZeroBundle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let remaining = self.len.saturating_sub(self.position);
+            let len = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+            let header = self
+                .header
+                .get(self.position as usize..)
+                .unwrap_or_default();
+            let from_header = len.min(header.len());
+            buf[..from_header].copy_from_slice(&header[..from_header]);
+            buf[from_header..len].fill(0);
+            self.position += len as u64;
+            Ok(len)
+        }
+    }
+
+    impl Seek for ZeroBundle {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset,
+                SeekFrom::End(offset) => self.len.saturating_add_signed(offset),
+                SeekFrom::Current(offset) => self.position.saturating_add_signed(offset),
+            };
+            Ok(self.position)
+        }
+    }
+
+    fn size_limit_kind(result: Result<()>) -> &'static str {
+        match result {
+            Err(BundleError::SizeLimitExceeded {
+                kind,
+                actual,
+                limit,
+            }) => {
+                assert!(actual > limit);
+                kind
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn android_size_limits() {
+        let android = Some(Platform::Android);
+        let encode = |bundle: &AssetBundle| {
+            AssetBundleEncoder::new(io::sink())
+                .platform(android)
+                .encode(bundle)
+        };
+        // Stored, so the compressed size tops 10 MiB well under 40 MiB
+        let mut bundle = sample_bundle(Compression::None, 1);
+        bundle.blocks = vec![sample_data(11 * 1024 * 1024)];
+        bundle.blocks_info[0].uncompressed_size = bundle.blocks[0].len() as u32;
+        assert_eq!(size_limit_kind(encode(&bundle)), "Compressed");
+
+        bundle.blocks = vec![vec![0; 41 * 1024 * 1024]];
+        bundle.blocks_info[0].uncompressed_size = bundle.blocks[0].len() as u32;
+        assert_eq!(size_limit_kind(encode(&bundle)), "Uncompressed");
+
+        bundle.blocks = vec![sample_data(9 * 1024 * 1024)];
+        bundle.blocks_info[0].uncompressed_size = bundle.blocks[0].len() as u32;
+        assert!(encode(&bundle).is_ok());
+    }
+
+    #[test]
+    fn pc_size_limits() {
+        let transcode = |input: ZeroBundle| {
+            AssetBundleEncoder::new(io::sink())
+                .platform(Some(Platform::Pc))
+                .transcode(AssetBundleDecoder::new(input), None, None)
+        };
+        // Declared sizes are checked before any data is read
+        let uncompressed = ZeroBundle::new(501 * 1024 * 1024);
+        assert_eq!(size_limit_kind(transcode(uncompressed)), "Uncompressed");
+        let compressed = ZeroBundle::new(201 * 1024 * 1024);
+        assert_eq!(size_limit_kind(transcode(compressed)), "Compressed");
+        assert!(transcode(ZeroBundle::new(1024 * 1024)).is_ok());
+    }
+
+    #[test]
+    fn lzma_presets_decode_identically() {
+        let bundle = sample_bundle(Compression::Lzma, 2);
+        let fast = encode_with(&bundle, |encoder| encoder.lzma_preset(1));
+        let small = encode_with(&bundle, |encoder| encoder.lzma_preset(9));
+        assert_ne!(fast, small);
+        assert_eq!(decode(&fast).blocks, decode(&small).blocks);
+
+        let fast = transcode_with(&small, None, |encoder| encoder.lzma_preset(1));
+        assert_eq!(decode(&fast).blocks, bundle.blocks);
+
+        // Out of range presets are clamped
+        let clamped = encode_with(&bundle, |encoder| encoder.lzma_preset(42));
+        assert_eq!(clamped, small);
+    }
+
+    /// Runs every reader over `bytes`, which must fail cleanly or succeed
+    /// but never panic. Returns whether `decode` succeeded.
+    fn read_all_ways(bytes: &[u8]) -> bool {
+        let decoder = || AssetBundleDecoder::new(Cursor::new(bytes.to_vec()));
+        let _ = decoder().decode_metadata();
+        let _ = decoder().validate();
+        let _ = decoder().padding_regions();
+        let _ = decoder().time_blocks();
+        let _ = decoder().decode_block(1);
+        decoder().decode().is_ok()
+    }
+
+    #[test]
+    fn parser_survives_truncated_and_corrupt_input() {
+        for compression in [
+            Compression::None,
+            Compression::Lzma,
+            Compression::Lz4,
+            Compression::Zstd,
+        ] {
+            let encoded = encode_with(&sample_bundle(compression, 3), |encoder| encoder);
+            for len in (0..encoded.len()).step_by(4999) {
+                assert!(!read_all_ways(&encoded[..len]), "{compression:?} {len}");
+            }
+
+            // Corrupt a few bytes of the header and block info at a time,
+            // from a fixed xorshift sequence so failures reproduce
+            let mut state = 0x2545_f491_4f6c_dd1d_u64;
+            let mut next = |bound: usize| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state % bound as u64) as usize
+            };
+            for _ in 0..200 {
+                let mut corrupt = encoded.clone();
+                for _ in 0..1 + next(4) {
+                    let index = next(256);
+                    corrupt[index] ^= 1 << next(8);
+                }
+                read_all_ways(&corrupt);
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_block_is_rejected_before_allocating() {
+        let bundle = sample_bundle(Compression::None, 3);
+        let mut encoded = encode_with(&bundle, |encoder| encoder);
+        let mut block_info = Vec::new();
+        decode(&encoded).blocks_info[0]
+            .write_to(&mut block_info)
+            .unwrap();
+        let start = encoded
+            .windows(block_info.len())
+            .position(|window| window == block_info)
+            .unwrap();
+        // Both sizes of the first block
+        encoded[start..start + 8].fill(0xFF);
+        let decoder = || AssetBundleDecoder::new(Cursor::new(encoded.clone()));
+        // Reading the whole bundle stops at the end of the input
+        assert!(decoder().decode().is_err());
+        let result = decoder().decode_block(0);
+        assert!(
+            matches!(result, Err(BundleError::InvalidData(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn lzma_dict_size_round_trips() {
+        let mut bundle = sample_bundle(Compression::Lzma, 1);
+        bundle.blocks = vec![sample_data(1_500_000)];
+        bundle.blocks_info[0].uncompressed_size = 1_500_000;
+        for (dict_size, expected) in [
+            // Sized to the block unless given, and clamped either way
+            (None, 2 * 1024 * 1024),
+            (Some(1024 * 1024), 1024 * 1024),
+            (Some(1), LZMA_MIN_DICT_SIZE),
+            (Some(u32::MAX), LZMA_MAX_DICT_SIZE),
+        ] {
+            let encoded = encode_with(&bundle, |encoder| encoder.lzma_dict_size(dict_size));
+            let decoded = decode(&encoded);
+            // The single block ends the file, its properties first
+            let block_start = encoded.len() - decoded.blocks_info[0].compressed_size as usize;
+            let props = &encoded[block_start..block_start + 5];
+            let stored = u32::from_le_bytes(props[1..].try_into().unwrap());
+            assert_eq!(stored, expected, "{dict_size:?}");
+            assert_eq!(decoded.blocks, bundle.blocks, "{dict_size:?}");
+        }
+    }
+
+    #[test]
+    fn info_at_end_round_trips() {
+        let mut bundle = sample_bundle(Compression::Lz4Hc, 3);
+        // Stored block info, so its directory is readable at the end
+        bundle.flags = (bundle.flags & !0x3F) | 0x80;
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        assert!(encoded.ends_with(b"CAB-sample.resS\0"));
+
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.flags, bundle.flags);
+        assert!(bundle.first_difference(&decoded).is_none());
+        assert_eq!(encode_with(&decoded, |encoder| encoder), encoded);
+
+        let transcoded = transcode_with(&encoded, Some(Compression::Lzma), |encoder| encoder);
+        assert!(transcoded.ends_with(b"CAB-sample.resS\0"));
+        let transcoded = decode(&transcoded);
+        assert_eq!(transcoded.flags, bundle.flags);
+        assert_eq!(transcoded.blocks, bundle.blocks);
+    }
+
+    #[test]
+    fn info_at_end_with_zstd_round_trips() {
+        let mut bundle = sample_bundle(Compression::Zstd, 3);
+        bundle.flags |= 0x80;
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        // The last zstd block is directly followed by the zstd block info,
+        // so each has to be read to exactly its size
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.flags, bundle.flags);
+        assert!(bundle.first_difference(&decoded).is_none());
+
+        let transcoded = decode(&transcode_with(
+            &encoded,
+            Some(Compression::Zstd),
+            |encoder| encoder.block_size(Some(32 * 1024)),
+        ));
+        assert_eq!(transcoded.flags, bundle.flags);
+        assert_eq!(transcoded.blocks.concat(), bundle.blocks.concat());
+    }
+
+    #[test]
+    fn version_8_header_is_aligned() {
+        // The 50 header bytes of the sample bundle are padded to 64
+        let bundle = sample_bundle(Compression::Lz4Hc, 3);
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.version, 8);
+        assert_eq!(decoded.header_extra, [0; 14]);
+        assert!(bundle.first_difference(&decoded).is_none());
+        let regions = AssetBundleDecoder::new(Cursor::new(encoded))
+            .padding_regions()
+            .unwrap();
+        assert_eq!((regions[0].offset, regions[0].length), (50, 14));
+        assert!(regions[0].zeroed);
+
+        // Version 6 predates the alignment
+        let mut old = sample_bundle(Compression::Lz4Hc, 3);
+        old.version = 6;
+        old.unity_revision = "2019.3.0f1".to_owned();
+        old.flags &= !0x200;
+        let decoded = decode(&encode_with(&old, |encoder| encoder));
+        assert_eq!(decoded.version, 6);
+        assert!(decoded.header_extra.is_empty());
+
+        let mut newer = sample_bundle(Compression::Lz4Hc, 3);
+        newer.version = 9;
+        let result = AssetBundleEncoder::new(Vec::new()).encode(&newer);
+        assert!(matches!(result, Err(BundleError::UnsupportedBundle(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_directory_path_is_rejected() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        // A Latin-1 name, as an old exporter might have written it
+        let name = OsStr::from_bytes(b"CAB-caf\xe9");
+        let mut bundle = sample_bundle(Compression::None, 1);
+        bundle.directory_info[0].path = "CAB-cafe".to_owned();
+        let mut encoded = encode_with(&bundle, |encoder| encoder);
+        let start = encoded
+            .windows(9)
+            .position(|window| window == b"CAB-cafe\0")
+            .unwrap();
+        encoded[start..start + 8].copy_from_slice(name.as_bytes());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(name);
+        std::fs::write(&path, &encoded).unwrap();
+        let file = BufReader::new(File::open(&path).unwrap());
+        match AssetBundleDecoder::new(file).decode_metadata() {
+            Err(BundleError::InvalidData(message)) => {
+                assert_eq!(message, "Directory path is not valid UTF-8")
+            }
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn absurd_counts_are_rejected() {
+        let expect_invalid =
+            |bytes: Vec<u8>, kind: &str| match AssetBundleDecoder::new(Cursor::new(bytes))
+                .decode_metadata()
+            {
+                Err(BundleError::InvalidData(message)) => {
+                    assert!(message.starts_with(kind), "{message}")
+                }
+                other => panic!("{other:?}"),
+            };
+
+        // Stored block info, located by the data hash it starts with
+        let bundle = sample_bundle(Compression::None, 3);
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let hash = decode(&encoded).data_hash;
+        let start = encoded.windows(16).position(|w| w == hash).unwrap() + 16;
+        let directory_count = start + 4 + 3 * BLOCK_INFO_LEN as usize;
+        for (offset, kind) in [(start, "Block count"), (directory_count, "Directory count")] {
+            let mut corrupt = encoded.clone();
+            corrupt[offset..offset + 4].fill(0xFF);
+            expect_invalid(corrupt, kind);
+        }
+
+        let mut raw = legacy_bundle(false, &[("CAB-old", b"data")]);
+        // The data is the count, one entry and its 4 bytes
+        let data_start = raw.len() - (4 + 8 + 8 + 4);
+        raw[data_start..data_start + 4].fill(0xFF);
+        expect_invalid(raw, "Directory count");
+    }
+
+    #[test]
+    fn block_info_read_write_symmetry() {
+        let block = BlockInfo {
+            uncompressed_size: 0x0102_0304,
+            compressed_size: 0xA0B0_C0D0,
+            flags: 0x4043,
+        };
+        let mut bytes = Vec::new();
+        block.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, BLOCK_INFO_LEN);
+        assert_eq!(
+            bytes,
+            [0x01, 0x02, 0x03, 0x04, 0xA0, 0xB0, 0xC0, 0xD0, 0x40, 0x43]
+        );
+        assert_eq!(BlockInfo::read_from(&mut bytes.as_slice()).unwrap(), block);
+
+        for block in [
+            BlockInfo {
+                uncompressed_size: 0,
+                compressed_size: 0,
+                flags: 0,
+            },
+            BlockInfo {
+                uncompressed_size: u32::MAX,
+                compressed_size: u32::MAX,
+                flags: u16::MAX,
+            },
+        ] {
+            let mut bytes = Vec::new();
+            block.write_to(&mut bytes).unwrap();
+            assert_eq!(BlockInfo::read_from(&mut bytes.as_slice()).unwrap(), block);
+        }
+    }
+
+    #[test]
+    fn corrupt_lzma_properties_are_rejected() {
+        let header = |props: u8, dict_size: u32| {
+            let mut header = [props; 5];
+            header[1..].copy_from_slice(&dict_size.to_le_bytes());
+            header
+        };
+        // lc=3, lp=0, pb=2, the usual properties, with any dictionary
+        assert!(check_lzma_properties(&header(0x5D, 4096)).is_ok());
+        assert!(check_lzma_properties(&header(224, LZMA_MAX_DICT_SIZE)).is_ok());
+        for corrupt in [
+            header(225, 4096),
+            header(0xFF, 4096),
+            header(0x5D, u32::MAX),
+        ] {
+            let result = check_lzma_properties(&corrupt);
+            assert!(
+                matches!(result, Err(BundleError::InvalidData(_))),
+                "{corrupt:?}"
+            );
+        }
+
+        // The properties open the only block, which ends the file
+        let bundle = sample_bundle(Compression::Lzma, 1);
+        let mut encoded = encode_with(&bundle, |encoder| encoder);
+        let block_start = encoded.len() - decode(&encoded).blocks_info[0].compressed_size as usize;
+        encoded[block_start] = 0xFF;
+        let result = AssetBundleDecoder::new(Cursor::new(encoded)).decode();
+        assert!(
+            matches!(result, Err(BundleError::InvalidData(_))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn empty_and_truncated_files_are_reported() {
+        let message = |bytes: &[u8]| {
+            let result = AssetBundleDecoder::new(Cursor::new(bytes.to_vec())).decode_metadata();
+            match result {
+                Err(BundleError::InvalidData(message)) => message,
+                other => panic!("{other:?}"),
+            }
+        };
+        assert_eq!(message(b""), "File is empty");
+        assert_eq!(
+            message(b"Unity"),
+            "File is truncated at byte 5, before the end of the signature"
+        );
+        assert_eq!(
+            message(b"UnityFS\0"),
+            "File is truncated at byte 8, before the end of the UnityFS header"
+        );
+
+        // Transcoding reads the header the same way
+        let result = AssetBundleEncoder::new(Vec::new()).transcode(
+            AssetBundleDecoder::new(Cursor::new(Vec::new())),
+            None,
+            None,
+        );
+        assert!(matches!(result, Err(BundleError::InvalidData(m)) if m == "File is empty"));
+    }
+
+    #[test]
+    fn replace_entry_rejects_overflowing_entry() {
+        let mut bundle = sample_bundle(Compression::Lz4, 3);
+        bundle.directory_info[1].offset = u64::MAX;
+        let result = bundle.replace_entry("CAB-sample", vec![1, 2, 3]);
+        assert!(matches!(result, Err(BundleError::InvalidData(_))));
+
+        let mut bundle = sample_bundle(Compression::Lz4, 3);
+        bundle.replace_entry("CAB-sample", vec![1, 2, 3]).unwrap();
+        assert_eq!(bundle.directory_info[1].offset, 3);
+        let entry = &bundle.directory_info[0];
+        assert_eq!(bundle.entry_data(entry).unwrap().as_ref(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn unknown_flags_and_header_bytes_round_trip() {
+        let mut bundle = sample_bundle(Compression::Lz4Hc, 3);
+        // Bits no known Unity version sets, on the archive and each block
+        bundle.flags |= 0x10000;
+        for block in &mut bundle.blocks_info {
+            block.flags |= 0x4000;
+        }
+        // The header's known fields end 14 bytes short of the alignment
+        bundle.header_extra = (1..=14).collect();
+
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.flags, bundle.flags);
+        assert_eq!(decoded.header_extra, bundle.header_extra);
+        assert!(decoded
+            .blocks_info
+            .iter()
+            .all(|block| block.flags == 0x4003));
+
+        let transcoded = decode(&transcode_with(
+            &encoded,
+            Some(Compression::Zstd),
+            |encoder| encoder,
+        ));
+        assert_eq!(transcoded.flags, bundle.flags);
+        assert_eq!(transcoded.header_extra, bundle.header_extra);
+        assert!(transcoded
+            .blocks_info
+            .iter()
+            .all(|block| block.flags == 0x4004));
+        assert!(bundle.first_difference(&transcoded).is_none());
+    }
+
+    #[test]
+    fn data_hash_covers_uncompressed_data() {
+        let bundle = sample_bundle(Compression::Lz4Hc, 3);
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let decoded = decode(&encoded);
+        assert_ne!(decoded.data_hash, [0; 16]);
+        assert_eq!(
+            decoded.data_hash.as_slice(),
+            Md5::digest(bundle.blocks.concat()).as_slice()
+        );
+
+        // Stable across a re-encode, whatever the compression and blocks
+        let reencoded = decode(&encode_with(&decoded, |encoder| {
+            encoder.block_size(Some(64 * 1024))
+        }));
+        assert_eq!(reencoded.data_hash, decoded.data_hash);
+    }
+
+    #[test]
+    fn transcode_writes_data_hash() {
+        let bundle = sample_bundle(Compression::Lzma, 3);
+        let encoded = encode_with(&bundle, |encoder| encoder);
+        let expected = decode(&encoded).data_hash;
+        // One thread compresses blocks as they stream in, several buffer
+        // them first
+        for threads in [1, 4] {
+            let transcoded = decode(&transcode_with(
+                &encoded,
+                Some(Compression::Lz4Hc),
+                |encoder| encoder.threads(threads).block_size(Some(48 * 1024)),
+            ));
+            assert_eq!(transcoded.data_hash, expected, "{threads} threads");
+        }
+    }
+
+    #[test]
+    fn foreign_data_hash_is_accepted() {
+        let bundle = sample_bundle(Compression::None, 3);
+        let mut encoded = encode_with(&bundle, |encoder| encoder);
+        let data_hash = decode(&encoded).data_hash;
+        // Unity stores its own Hash128 here, which isn't an MD5 of the data
+        let start = encoded
+            .windows(16)
+            .position(|window| window == data_hash)
+            .unwrap();
+        encoded[start..start + 16].copy_from_slice(&[0xAB; 16]);
+
+        let decoded = decode(&encoded);
+        assert_eq!(decoded.data_hash, [0xAB; 16]);
+        assert_eq!(decoded.blocks, bundle.blocks);
+        let transcoded = decode(&transcode_with(
+            &encoded,
+            Some(Compression::Lz4),
+            |encoder| encoder,
+        ));
+        assert_eq!(transcoded.data_hash, data_hash);
+    }
+
+    #[test]
+    fn unity_raw_converts_to_unity_fs() {
+        let serialized = sample_data(5000);
+        let resource = sample_data(20000);
+        let raw = legacy_bundle(
+            false,
+            &[("CAB-old", &serialized), ("CAB-old.resS", &resource)],
+        );
+        let legacy = decode(&raw);
+        assert_eq!(legacy.format(), Some(ArchiveFormat::UnityRaw));
+
+        let encoded = decode(&encode_with(&legacy, |encoder| encoder));
+        let transcoded = decode(&transcode_with(&raw, Some(Compression::Lz4Hc), |encoder| {
+            encoder
+        }));
+        for converted in [&encoded, &transcoded] {
+            assert_eq!(converted.format(), Some(ArchiveFormat::UnityFs));
+            assert_eq!(converted.version, 6);
+            assert_eq!(converted.unity_revision, "5.2.0f3");
+            let [entry, resource_entry] = converted.directory_info() else {
+                panic!("{:?}", converted.directory_info());
+            };
+            assert!(entry.is_serialized_file());
+            assert!(!resource_entry.is_serialized_file());
+            assert_eq!(converted.entry_data(entry).unwrap().as_ref(), serialized);
+            assert_eq!(
+                converted.entry_data(resource_entry).unwrap().as_ref(),
+                resource
+            );
+        }
+    }
+
+    #[test]
+    fn unity_web_converts_to_unity_fs() {
+        let serialized = sample_data(50000);
+        let web = legacy_bundle(true, &[("CAB-web", &serialized)]);
+        let legacy = decode(&web);
+        assert_eq!(legacy.format(), Some(ArchiveFormat::UnityWeb));
+
+        // The `.lzma` stream becomes an LZMA block unless recompressed
+        let encoded = decode(&encode_with(&legacy, |encoder| encoder));
+        assert_eq!(
+            encoded.blocks_info[0].flags,
+            Compression::Lzma.flags() as u16
+        );
+        let transcoded = decode(&transcode_with(&web, None, |encoder| encoder));
+        let recompressed = decode(&transcode_with(&web, Some(Compression::Zstd), |encoder| {
+            encoder.block_size(Some(16 * 1024))
+        }));
+        for converted in [&encoded, &transcoded, &recompressed] {
+            assert_eq!(converted.format(), Some(ArchiveFormat::UnityFs));
+            let entry = &converted.directory_info()[0];
+            assert!(entry.is_serialized_file());
+            assert_eq!(converted.entry_data(entry).unwrap().as_ref(), serialized);
+        }
+        assert!(encoded.first_difference(&transcoded).is_none());
+    }
+
+    #[test]
+    fn lz4_blocks_round_trip() {
+        for compression in [Compression::Lz4, Compression::Lz4Hc] {
+            let bundle = sample_bundle(compression, 3);
+            let decoded = decode(&encode_with(&bundle, |encoder| encoder.lz4hc_level(12)));
+            assert_eq!(decoded.blocks, bundle.blocks, "{compression:?}");
+        }
+    }
+}
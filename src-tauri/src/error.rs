@@ -0,0 +1,153 @@
+//! Error returned by the Tauri commands. It reaches the frontend as
+//! `{ kind, message }`, plus `code` and `body` for HTTP status errors and
+//! `actual` and `limit` for size limit errors, so the UI can branch on
+//! `kind` instead of matching message text.
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::bundle::BundleError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("{0}")]
+    Io(String),
+    #[error("{0}")]
+    Network(String),
+    /// The configured proxy couldn't be reached or refused the request.
+    #[error("{0}")]
+    Proxy(String),
+    #[error("{0}")]
+    Keyring(String),
+    #[error("{0}")]
+    Bundle(String),
+    /// A bundle is over a platform size limit, in bytes.
+    #[error("{message}")]
+    SizeLimit {
+        message: String,
+        actual: u64,
+        limit: u64,
+    },
+    #[error("{code}: {body}")]
+    HttpStatus { code: u16, body: String },
+    /// What was cancelled, e.g. "Upload".
+    #[error("{0} cancelled")]
+    Cancelled(&'static str),
+    #[error("{0}")]
+    Other(String),
+}
+
+impl CommandError {
+    fn kind(&self) -> &'static str {
+        match self {
+            CommandError::Io(_) => "io",
+            CommandError::Network(_) => "network",
+            CommandError::Proxy(_) => "proxy",
+            CommandError::Keyring(_) => "keyring",
+            CommandError::Bundle(_) => "bundle",
+            CommandError::SizeLimit { .. } => "sizeLimit",
+            CommandError::HttpStatus { .. } => "httpStatus",
+            CommandError::Cancelled(_) => "cancelled",
+            CommandError::Other(_) => "other",
+        }
+    }
+
+    /// Notes how many attempts were made before giving up.
+    pub fn after_attempts(self, attempts: u32) -> Self {
+        let note = |message: String| format!("{} (after {} attempts)", message, attempts);
+        match self {
+            CommandError::Io(message) => CommandError::Io(note(message)),
+            CommandError::Network(message) => CommandError::Network(note(message)),
+            CommandError::Proxy(message) => CommandError::Proxy(note(message)),
+            CommandError::Keyring(message) => CommandError::Keyring(note(message)),
+            CommandError::Bundle(message) => CommandError::Bundle(note(message)),
+            CommandError::SizeLimit {
+                message,
+                actual,
+                limit,
+            } => CommandError::SizeLimit {
+                message: note(message),
+                actual,
+                limit,
+            },
+            CommandError::HttpStatus { code, body } => CommandError::HttpStatus {
+                code,
+                body: note(body),
+            },
+            CommandError::Cancelled(what) => CommandError::Cancelled(what),
+            CommandError::Other(message) => CommandError::Other(note(message)),
+        }
+    }
+}
+
+impl Serialize for CommandError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = match self {
+            CommandError::HttpStatus { .. } | CommandError::SizeLimit { .. } => 4,
+            _ => 2,
+        };
+        let mut state = serializer.serialize_struct("CommandError", len)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        match self {
+            CommandError::HttpStatus { code, body } => {
+                state.serialize_field("code", code)?;
+                state.serialize_field("body", body)?;
+            }
+            CommandError::SizeLimit { actual, limit, .. } => {
+                state.serialize_field("actual", actual)?;
+                state.serialize_field("limit", limit)?;
+            }
+            _ => {}
+        }
+        state.end()
+    }
+}
+
+impl From<std::io::Error> for CommandError {
+    fn from(err: std::io::Error) -> Self {
+        CommandError::Io(err.to_string())
+    }
+}
+
+impl From<reqwest::Error> for CommandError {
+    fn from(err: reqwest::Error) -> Self {
+        CommandError::Network(err.to_string())
+    }
+}
+
+impl From<keyring::Error> for CommandError {
+    fn from(err: keyring::Error) -> Self {
+        CommandError::Keyring(err.to_string())
+    }
+}
+
+impl From<BundleError> for CommandError {
+    fn from(err: BundleError) -> Self {
+        match err {
+            BundleError::Io(err) => err.into(),
+            // Only transcodes are given a cancellation token
+            BundleError::Cancelled => CommandError::Cancelled("Transcode"),
+            BundleError::SizeLimitExceeded { actual, limit, .. } => CommandError::SizeLimit {
+                message: err.to_string(),
+                actual,
+                limit,
+            },
+            err => CommandError::Bundle(err.to_string()),
+        }
+    }
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError::Other(message)
+    }
+}
+
+impl From<&str> for CommandError {
+    fn from(message: &str) -> Self {
+        CommandError::Other(message.to_owned())
+    }
+}
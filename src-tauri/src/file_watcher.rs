@@ -0,0 +1,157 @@
+//! Watches an exported bundle and emits `bundle://changed` once it stops
+//! being written to, so the frontend can offer a re-upload.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, State};
+
+use crate::error::CommandError;
+
+const CHANGED_EVENT: &str = "bundle://changed";
+
+/// Unity writes bundles in bursts, so wait this long without further writes
+/// before reporting a change.
+const DEBOUNCE: Duration = Duration::from_millis(750);
+
+/// The active watch, if any. Dropping the watcher releases the OS watch and
+/// ends its debounce thread.
+#[derive(Default)]
+pub struct FileWatcherState(Mutex<Option<RecommendedWatcher>>);
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleChanged {
+    path: String,
+}
+
+/// Whether `event` writes to `target`. The whole directory is watched, so
+/// events for other files in it are ignored.
+fn writes_to(event: &Event, target: &Path) -> bool {
+    matches!(
+        event.kind,
+        EventKind::Create(_) | EventKind::Modify(_) | EventKind::Any
+    ) && event.paths.iter().any(|path| path == target)
+}
+
+/// Waits for writes to `target` and emits one event per burst of them.
+fn debounce(app: AppHandle, target: PathBuf, events: mpsc::Receiver<Event>) {
+    loop {
+        // Idle until something touches the file
+        match events.recv() {
+            Ok(event) if writes_to(&event, &target) => {}
+            Ok(_) => continue,
+            Err(_) => return,
+        }
+        // Then until it has been quiet for a while. Only writes to the file
+        // push the deadline back, not activity elsewhere in the directory.
+        let mut deadline = Instant::now() + DEBOUNCE;
+        loop {
+            match events.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) if writes_to(&event, &target) => deadline = Instant::now() + DEBOUNCE,
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+        if target.exists() {
+            let _ = app.emit(
+                CHANGED_EVENT,
+                BundleChanged {
+                    path: target.to_string_lossy().into_owned(),
+                },
+            );
+        }
+    }
+}
+
+/// Starts watching `path`, replacing any previous watch.
+#[tauri::command]
+pub fn start_watch(
+    app: AppHandle,
+    state: State<'_, FileWatcherState>,
+    path: String,
+) -> Result<(), CommandError> {
+    crate::catch_panics(|| {
+        let (parent, target) = watch_target(&path)?;
+        watch(app, &state, parent, target)
+    })
+}
+
+/// The directory to watch for `path` and the canonical path of the file.
+fn watch_target(path: &str) -> Result<(PathBuf, PathBuf), CommandError> {
+    let path = Path::new(path);
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return Err(format!("Can't watch {}", path.display()).into());
+    };
+    if !path.is_file() {
+        return Err(format!("{} does not exist", path.display()).into());
+    }
+    // The parent is watched because Unity may replace the file rather than
+    // write to it in place
+    let parent = if parent.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        parent
+    };
+    let parent = parent.canonicalize()?;
+    let target = parent.join(file_name);
+    Ok((parent, target))
+}
+
+fn watch(
+    app: AppHandle,
+    state: &FileWatcherState,
+    parent: PathBuf,
+    target: PathBuf,
+) -> Result<(), CommandError> {
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event);
+        }
+    })
+    .map_err(|e| e.to_string())?;
+    watcher
+        .watch(&parent, RecursiveMode::NonRecursive)
+        .map_err(|e| e.to_string())?;
+
+    std::thread::spawn(move || debounce(app, target, receiver));
+    *state.0.lock().unwrap_or_else(|err| err.into_inner()) = Some(watcher);
+    Ok(())
+}
+
+#[tauri::command]
+pub fn stop_watch(state: State<'_, FileWatcherState>) -> Result<(), CommandError> {
+    state.0.lock().unwrap_or_else(|err| err.into_inner()).take();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_target_rejects_malformed_paths() {
+        assert!(watch_target("").is_err());
+        assert!(watch_target("/").is_err());
+        assert!(watch_target("..").is_err());
+        let dir = tempfile::tempdir().unwrap();
+        assert!(watch_target(dir.path().to_str().unwrap()).is_err());
+        assert!(watch_target(dir.path().join("missing").to_str().unwrap()).is_err());
+
+        let file = dir.path().join("avatar.vrca");
+        std::fs::write(&file, b"").unwrap();
+        let (parent, target) = watch_target(file.to_str().unwrap()).unwrap();
+        assert_eq!(parent, dir.path().canonicalize().unwrap());
+        assert_eq!(target, parent.join("avatar.vrca"));
+    }
+}
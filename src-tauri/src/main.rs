@@ -4,183 +4,3093 @@
 )]
 
 use std::{
+    collections::HashMap,
     fs::File,
-    io::{BufReader, SeekFrom, Write},
-    path::PathBuf,
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
 };
 
+use assets::{MaterialStats, MeshStats, TextureInfo};
 use base64::{engine::general_purpose::STANDARD, Engine as _};
-use bundle::{AssetBundleDecoder, AssetBundleEncoder};
+use bundle::{
+    AssetBundle, AssetBundleDecoder, AssetBundleEncoder, BundleDifference, BundleInfo, Compression,
+    CountingWriter, PaddingRegion, Platform, TranscodeStage, ValidationCheck, LZ4HC_DEFAULT_LEVEL,
+};
+use error::CommandError;
+use futures_util::{StreamExt, TryStreamExt};
 use keyring::Entry;
 use librsync::Signature;
 use md5::{Digest, Md5};
 use rand::Rng;
-use reqwest::{header::*, Body};
+use reqwest::{header::*, Body, StatusCode};
 use serde::{Deserialize, Serialize};
-use tauri::{path, AppHandle, Manager, Url};
+use serialized::SerializedFile;
+use settings::TranscodeDefaults;
+use sha2::Sha256;
+use tauri::{path, AppHandle, Emitter, Manager, State, Url};
 use tokio::io::{AsyncReadExt, AsyncSeekExt};
 use tokio_util::{
     codec::{BytesCodec, FramedRead},
     io::ReaderStream,
+    sync::CancellationToken,
 };
 use zip::ZipArchive;
 
-//   mod file_watcher;
-mod bundle;
-mod upload;
+mod assets;
+mod bundle;
+mod error;
+mod file_watcher;
+mod remote;
+mod serialized;
+mod settings;
+mod upload;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct Token {
+    auth: String,
+    two_factor: String,
+}
+
+/// Serializes keyring writes so a compare-and-swap can't interleave with
+/// another save.
+static TOKEN_LOCK: Mutex<()> = Mutex::new(());
+
+/// Keyring entry listing the usernames with a stored token, since not every
+/// keyring backend can enumerate entries.
+fn account_index_entry() -> Result<Entry, CommandError> {
+    Entry::new("third_vrchat_token_index", "accounts").map_err(CommandError::from)
+}
+
+fn load_account_index() -> Result<Vec<String>, CommandError> {
+    match account_index_entry()?.get_password() {
+        // A corrupt index is rebuilt from scratch by later saves
+        Ok(json) => Ok(serde_json::from_str(&json).unwrap_or_default()),
+        Err(keyring::Error::NoEntry) => Ok(Vec::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn save_account_index(usernames: &[String]) -> Result<(), CommandError> {
+    let json = serde_json::to_string(usernames).map_err(|e| e.to_string())?;
+    account_index_entry()?
+        .set_password(&json)
+        .map_err(CommandError::from)
+}
+
+fn update_account_index(username: &str, present: bool) -> Result<(), CommandError> {
+    let mut usernames = load_account_index()?;
+    let listed = usernames.iter().any(|name| name == username);
+    if present && !listed {
+        usernames.push(username.to_owned());
+    } else if !present && listed {
+        usernames.retain(|name| name != username);
+    } else {
+        return Ok(());
+    }
+    save_account_index(&usernames)
+}
+
+#[tauri::command]
+async fn save_token(username: String, token: Token) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let _guard = TOKEN_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let entry = Entry::new("third_vrchat_token", &username)?;
+        let json = serde_json::to_string(&token).map_err(|e| e.to_string())?;
+        entry.set_password(&json)?;
+        update_account_index(&username, true)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn load_token(username: String) -> Result<Option<Token>, CommandError> {
+    run_blocking(move || {
+        let entry = Entry::new("third_vrchat_token", &username)?;
+        let res = entry.get_password();
+        match res {
+            Ok(json) => serde_json::from_str::<Token>(&json)
+                .map(|t| Some(t))
+                .map_err(|e| e.to_string().into()),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    })
+    .await
+}
+
+#[tauri::command]
+async fn delete_token(username: String) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let _guard = TOKEN_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let entry = Entry::new("third_vrchat_token", &username)?;
+        let result = entry.delete_credential();
+        // Also drop the name when the token was already gone
+        if matches!(result, Ok(()) | Err(keyring::Error::NoEntry)) {
+            update_account_index(&username, false)?;
+        }
+        result.map_err(CommandError::from)
+    })
+    .await
+}
+
+/// Saves `new` only if the stored token still equals `expected_current`,
+/// or if there is no stored token when `expected_current` is `None`.
+#[tauri::command]
+async fn save_token_cas(
+    username: String,
+    expected_current: Option<Token>,
+    new: Token,
+) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let _guard = TOKEN_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let entry = Entry::new("third_vrchat_token", &username)?;
+        let current = match entry.get_password() {
+            // An unreadable token never matches what the caller expects
+            Ok(json) => Some(serde_json::from_str::<Token>(&json).ok()),
+            Err(keyring::Error::NoEntry) => None,
+            Err(err) => return Err(err.into()),
+        };
+        let matches = match (&current, &expected_current) {
+            (None, None) => true,
+            (Some(Some(current)), Some(expected)) => current == expected,
+            _ => false,
+        };
+        if !matches {
+            return Err("Conflict: the stored token has changed".into());
+        }
+
+        let json = serde_json::to_string(&new).map_err(|e| e.to_string())?;
+        entry.set_password(&json)?;
+        update_account_index(&username, true)
+    })
+    .await
+}
+
+/// Moves the stored token from `old_username` to `new_username`, for when
+/// an account is renamed or its login changes. An existing token under the
+/// new name is only replaced when `overwrite` is set.
+#[tauri::command]
+async fn migrate_token(
+    old_username: String,
+    new_username: String,
+    overwrite: Option<bool>,
+) -> Result<(), CommandError> {
+    run_blocking(move || {
+        if old_username == new_username {
+            return Ok(());
+        }
+        let _guard = TOKEN_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let old_entry = Entry::new("third_vrchat_token", &old_username)?;
+        let json = match old_entry.get_password() {
+            Ok(json) => json,
+            Err(keyring::Error::NoEntry) => {
+                return Err(format!("No token is stored for {}", old_username).into())
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let new_entry = Entry::new("third_vrchat_token", &new_username)?;
+        if !overwrite.unwrap_or(false) {
+            match new_entry.get_password() {
+                Ok(_) => {
+                    return Err(format!("A token is already stored for {}", new_username).into())
+                }
+                Err(keyring::Error::NoEntry) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        // Written before the old entry goes, so a failure never loses it
+        new_entry.set_password(&json)?;
+        match old_entry.delete_credential() {
+            Ok(()) | Err(keyring::Error::NoEntry) => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        let mut usernames = load_account_index()?;
+        usernames.retain(|name| name != &old_username && name != &new_username);
+        usernames.push(new_username);
+        save_account_index(&usernames)
+    })
+    .await
+}
+
+/// Usernames with a stored token. Names whose token was removed outside of
+/// the app are left out and pruned from the index.
+#[tauri::command]
+async fn list_accounts() -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let _guard = TOKEN_LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        let usernames = load_account_index()?;
+        let mut present = Vec::with_capacity(usernames.len());
+        for username in &usernames {
+            let entry = Entry::new("third_vrchat_token", username)?;
+            match entry.get_password() {
+                Ok(_) => present.push(username.clone()),
+                Err(keyring::Error::NoEntry) => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if present.len() != usernames.len() {
+            save_account_index(&present)?;
+        }
+        Ok(present)
+    })
+    .await
+}
+
+/// Checks a stored token is present and well-formed without handing the
+/// secret itself to the frontend.
+#[tauri::command]
+async fn token_exists_and_valid(username: String) -> Result<bool, CommandError> {
+    run_blocking(move || {
+        let entry = Entry::new("third_vrchat_token", &username)?;
+        match entry.get_password() {
+            Ok(json) => Ok(serde_json::from_str::<Token>(&json)
+                .map(|token| !token.auth.is_empty() && !token.two_factor.is_empty())
+                .unwrap_or(false)),
+            Err(keyring::Error::NoEntry) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    })
+    .await
+}
+
+/// Runs blocking command work off the async runtime. A panic in `f` comes
+/// back as an error rather than leaving the command unresolved.
+async fn run_blocking<T, F>(f: F) -> Result<T, CommandError>
+where
+    F: FnOnce() -> Result<T, CommandError> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|err| match err.try_into_panic() {
+            Ok(panic) => panic_error(&*panic),
+            Err(err) => CommandError::Other(err.to_string()),
+        })?
+}
+
+/// Runs the body of a synchronous command, which Tauri calls on the main
+/// thread, turning a panic into an error like `run_blocking` does.
+fn catch_panics<T>(f: impl FnOnce() -> Result<T, CommandError>) -> Result<T, CommandError> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f))
+        .unwrap_or_else(|panic| Err(panic_error(&*panic)))
+}
+
+fn panic_error(panic: &(dyn std::any::Any + Send)) -> CommandError {
+    CommandError::Other(format!("Internal error: {}", panic_message(panic)))
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+#[tauri::command]
+async fn md5_digest_file(path: String) -> Result<String, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        let mut hasher = Md5::new();
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(chunk);
+            let len = chunk.len();
+            reader.consume(len);
+        }
+        let hashb64 = STANDARD.encode(hasher.finalize());
+        Ok(hashb64)
+    })
+    .await
+}
+
+/// Computes each of `algos` ("md5", "sha256") over `path` in a single read
+/// of the file, returning base64 digests keyed by algorithm name.
+#[tauri::command]
+async fn digest_file(
+    path: String,
+    algos: Vec<String>,
+) -> Result<HashMap<String, String>, CommandError> {
+    let mut md5 = None;
+    let mut sha256 = None;
+    for algo in &algos {
+        match algo.to_ascii_lowercase().as_str() {
+            "md5" => md5 = Some(Md5::new()),
+            "sha256" => sha256 = Some(Sha256::new()),
+            other => return Err(format!("Unknown digest algorithm: {}", other).into()),
+        }
+    }
+    if algos.is_empty() {
+        return Err("No digest algorithm requested".into());
+    }
+
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            if let Some(hasher) = &mut md5 {
+                hasher.update(chunk);
+            }
+            if let Some(hasher) = &mut sha256 {
+                hasher.update(chunk);
+            }
+            let len = chunk.len();
+            reader.consume(len);
+        }
+
+        let mut digests = HashMap::new();
+        if let Some(hasher) = md5 {
+            digests.insert("md5".to_owned(), STANDARD.encode(hasher.finalize()));
+        }
+        if let Some(hasher) = sha256 {
+            digests.insert("sha256".to_owned(), STANDARD.encode(hasher.finalize()));
+        }
+        Ok(digests)
+    })
+    .await
+}
+
+const SIGNATURE_BLOCK_LEN: usize = 2048;
+const SIGNATURE_STRONG_LEN: usize = 32;
+const SIGNATURE_HEADER_LEN: u64 = 12;
+const BLAKE2_SIG_MAGIC: u32 = 0x72730137;
+
+/// Granularity of a Blake2 signature, 2048-byte blocks with 32-byte strong
+/// sums unless the caller asks otherwise.
+#[derive(Clone, Copy)]
+struct SignatureOptions {
+    block_len: usize,
+    strong_len: usize,
+}
+
+impl SignatureOptions {
+    fn new(block_len: Option<usize>, strong_len: Option<usize>) -> Result<Self, String> {
+        let block_len = block_len.unwrap_or(SIGNATURE_BLOCK_LEN);
+        let strong_len = strong_len.unwrap_or(SIGNATURE_STRONG_LEN);
+        if block_len == 0 || block_len > u32::MAX as usize {
+            return Err(format!("Invalid signature block length: {}", block_len));
+        }
+        if !(1..=SIGNATURE_STRONG_LEN).contains(&strong_len) {
+            return Err(format!(
+                "Signature strong sum length must be 1 to {} bytes",
+                SIGNATURE_STRONG_LEN
+            ));
+        }
+        Ok(Self {
+            block_len,
+            strong_len,
+        })
+    }
+}
+
+fn signature_of<R: Read>(input: R, options: SignatureOptions) -> Result<Signature<R>, String> {
+    Signature::with_options(
+        input,
+        options.block_len,
+        options.strong_len,
+        librsync::SignatureType::Blake2,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Continues a partially written signature in `output`, keeping the blocks
+/// already signed. Returns `false` when the partial output can't be trusted.
+fn resume_signature(path: &str, output: &str, options: SignatureOptions) -> Result<bool, String> {
+    let mut partial = match std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(output)
+    {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err.to_string()),
+    };
+
+    let mut expected_header = Vec::with_capacity(SIGNATURE_HEADER_LEN as usize);
+    expected_header.extend_from_slice(&BLAKE2_SIG_MAGIC.to_be_bytes());
+    expected_header.extend_from_slice(&(options.block_len as u32).to_be_bytes());
+    expected_header.extend_from_slice(&(options.strong_len as u32).to_be_bytes());
+    let mut header = [0u8; SIGNATURE_HEADER_LEN as usize];
+    if partial.read_exact(&mut header).is_err() || header[..] != expected_header[..] {
+        return Ok(false);
+    }
+
+    // A block that was cut off mid-write is dropped and signed again
+    let record_len = 4 + options.strong_len as u64;
+    let partial_len = partial.metadata().map_err(|e| e.to_string())?.len();
+    let signed_blocks = (partial_len - SIGNATURE_HEADER_LEN) / record_len;
+
+    let mut input = File::open(path).map_err(|e| e.to_string())?;
+    let input_len = input.metadata().map_err(|e| e.to_string())?.len();
+    if signed_blocks > input_len.div_ceil(options.block_len as u64) {
+        return Ok(false);
+    }
+
+    // Re-sign the last recorded block to check the partial output belongs
+    // to this input
+    if signed_blocks > 0 {
+        let last_block = signed_blocks - 1;
+        let mut recorded = vec![0u8; record_len as usize];
+        partial
+            .seek(SeekFrom::Start(
+                SIGNATURE_HEADER_LEN + last_block * record_len,
+            ))
+            .map_err(|e| e.to_string())?;
+        partial
+            .read_exact(&mut recorded)
+            .map_err(|e| e.to_string())?;
+
+        input
+            .seek(SeekFrom::Start(last_block * options.block_len as u64))
+            .map_err(|e| e.to_string())?;
+        let mut computed = Vec::new();
+        signature_of((&input).take(options.block_len as u64), options)?
+            .read_to_end(&mut computed)
+            .map_err(|e| e.to_string())?;
+        if computed.get(SIGNATURE_HEADER_LEN as usize..) != Some(&recorded[..]) {
+            return Ok(false);
+        }
+    }
+
+    partial
+        .set_len(SIGNATURE_HEADER_LEN + signed_blocks * record_len)
+        .map_err(|e| e.to_string())?;
+    partial.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    input
+        .seek(SeekFrom::Start(signed_blocks * options.block_len as u64))
+        .map_err(|e| e.to_string())?;
+
+    let mut signature = signature_of(BufReader::new(input), options)?;
+    let mut header = [0u8; SIGNATURE_HEADER_LEN as usize];
+    signature
+        .read_exact(&mut header)
+        .map_err(|e| e.to_string())?;
+    std::io::copy(&mut signature, &mut partial).map_err(|e| e.to_string())?;
+    partial.sync_all().map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// With `resume`, an interrupted signature in `output` is continued rather
+/// than regenerated, falling back to a full run if it can't be trusted.
+/// `block_len` and `strong_len` default to 2048 and 32 bytes.
+#[tauri::command]
+async fn signature_generate_from_file(
+    path: String,
+    output: String,
+    resume: Option<bool>,
+    block_len: Option<usize>,
+    strong_len: Option<usize>,
+) -> Result<(), CommandError> {
+    let options = SignatureOptions::new(block_len, strong_len)?;
+    run_blocking(move || {
+        if resume.unwrap_or(false) && resume_signature(&path, &output, options)? {
+            return Ok(());
+        }
+
+        let file = std::fs::File::open(&path)?;
+        let mut signature = signature_of(&file, options)?;
+        let mut output_file = std::fs::File::create(&output)?;
+        std::io::copy(&mut signature, &mut output_file)?;
+        output_file.sync_all()?;
+        Ok(())
+    })
+    .await
+}
+
+/// Like `signature_generate_from_file`, but returns the signature as base64
+/// instead of writing it to a file.
+#[tauri::command]
+async fn signature_generate_bytes(
+    path: String,
+    block_len: Option<usize>,
+    strong_len: Option<usize>,
+) -> Result<String, CommandError> {
+    let options = SignatureOptions::new(block_len, strong_len)?;
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let mut signature = Vec::new();
+        signature_of(BufReader::new(file), options)?.read_to_end(&mut signature)?;
+        Ok(STANDARD.encode(signature))
+    })
+    .await
+}
+
+/// Loads a signature and prepares a delta of `new_file_path` against it.
+/// The delta is produced lazily as it is read.
+fn open_delta(
+    signature_path: &str,
+    new_file_path: &str,
+) -> Result<librsync::Delta<BufReader<File>>, String> {
+    let mut signature = BufReader::new(File::open(signature_path).map_err(|e| e.to_string())?);
+    let new_file = File::open(new_file_path).map_err(|e| e.to_string())?;
+    librsync::Delta::new(new_file, &mut signature).map_err(|e| e.to_string())
+}
+
+/// Writes the delta of `new_file_path` against the signature at
+/// `signature_path` to `output`, returning the delta's size in bytes.
+#[tauri::command]
+async fn delta_generate_from_file(
+    signature_path: String,
+    new_file_path: String,
+    output: String,
+) -> Result<u64, CommandError> {
+    run_blocking(move || {
+        let mut delta = open_delta(&signature_path, &new_file_path)?;
+        let mut output_file = File::create(&output)?;
+        let delta_size = std::io::copy(&mut delta, &mut output_file)?;
+        output_file.sync_all()?;
+        Ok(delta_size)
+    })
+    .await
+}
+
+/// Rebuilds a file from `basis_path` and a delta made against its
+/// signature, writing it to `output` and returning its size in bytes.
+#[tauri::command]
+async fn patch_apply(
+    basis_path: String,
+    delta_path: String,
+    output: String,
+) -> Result<u64, CommandError> {
+    run_blocking(move || {
+        let basis = BufReader::new(File::open(&basis_path)?);
+        let delta = BufReader::new(File::open(&delta_path)?);
+        let mut patch = librsync::Patch::new(basis, delta).map_err(|e| e.to_string())?;
+        let mut output_file = File::create(&output)?;
+        let size = std::io::copy(&mut patch, &mut output_file)?;
+        output_file.sync_all()?;
+        Ok(size)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DeltaEstimate {
+    delta_size: u64,
+    full_size: u64,
+    saved_bytes: i64,
+    saved_percent: f64,
+}
+
+#[tauri::command]
+async fn estimate_delta_savings(
+    old_signature: String,
+    new_file: String,
+) -> Result<DeltaEstimate, CommandError> {
+    run_blocking(move || {
+        let full_size = std::fs::metadata(&new_file)?.len();
+        let mut delta = open_delta(&old_signature, &new_file)?;
+        // Only the size is needed, so the delta itself is discarded
+        let delta_size = std::io::copy(&mut delta, &mut std::io::sink())?;
+
+        let saved_bytes = full_size as i64 - delta_size as i64;
+        let saved_percent = if full_size == 0 {
+            0.0
+        } else {
+            saved_bytes as f64 / full_size as f64 * 100.0
+        };
+        Ok(DeltaEstimate {
+            delta_size,
+            full_size,
+            saved_bytes,
+            saved_percent,
+        })
+    })
+    .await
+}
+
+/// Where `unpack_bundle` extracts archives, one subdirectory each.
+fn bundles_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    let app_dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(app_dir.join("bundles"))
+}
+
+/// Resolves `dir` to an extraction directly inside the bundles directory,
+/// refusing anything else.
+fn managed_bundle_dir(app: &AppHandle, dir: &str) -> Result<PathBuf, String> {
+    let root = bundles_dir(app)?
+        .canonicalize()
+        .map_err(|e| e.to_string())?;
+    let dir = Path::new(dir).canonicalize().map_err(|e| e.to_string())?;
+    if dir.parent() != Some(root.as_path()) || !dir.is_dir() {
+        return Err(format!(
+            "{} is not an unpacked bundle directory",
+            dir.display()
+        ));
+    }
+    Ok(dir)
+}
+
+/// Deletes one directory created by `unpack_bundle`.
+#[tauri::command]
+async fn remove_bundle(app: AppHandle, dir: String) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let dir = managed_bundle_dir(&app, &dir)?;
+        std::fs::remove_dir_all(dir).map_err(CommandError::from)
+    })
+    .await
+}
+
+/// Deletes every directory created by `unpack_bundle` except `keep`,
+/// returning how many were removed.
+#[tauri::command]
+async fn cleanup_bundles(app: AppHandle, keep: Option<String>) -> Result<usize, CommandError> {
+    run_blocking(move || {
+        let root = bundles_dir(&app)?;
+        if !root.exists() {
+            return Ok(0);
+        }
+        let keep = keep
+            .map(|keep| managed_bundle_dir(&app, &keep))
+            .transpose()?;
+
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&root)? {
+            let entry = entry?;
+            let file_type = entry.file_type()?;
+            // Symlinks are left alone so nothing outside gets followed
+            if !file_type.is_dir() {
+                continue;
+            }
+            let dir = entry.path().canonicalize()?;
+            if keep.as_ref() == Some(&dir) {
+                continue;
+            }
+            std::fs::remove_dir_all(&dir)?;
+            removed += 1;
+        }
+        Ok(removed)
+    })
+    .await
+}
+
+/// Extracts every entry below `dst`, refusing entries whose path is
+/// absolute or climbs out of it.
+fn extract_archive<R: Read + Seek>(archive: &mut ZipArchive<R>, dst: &Path) -> Result<(), String> {
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|e| e.to_string())?;
+        let Some(relative) = entry.enclosed_name() else {
+            return Err(format!(
+                "Archive entry {:?} points outside the extraction directory",
+                entry.name()
+            ));
+        };
+        if entry.is_symlink() {
+            return Err(format!("Archive entry {:?} is a symlink", entry.name()));
+        }
+
+        let target = dst.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut output = File::create(&target).map_err(|e| e.to_string())?;
+        std::io::copy(&mut entry, &mut output).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn unpack_bundle(app_handle: tauri::AppHandle, path: String) -> Result<String, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let reader = BufReader::new(file);
+        let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
+        let random_bytes: [u8; 16] = rand::thread_rng().gen();
+        let target_dir = random_bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+
+        let dst = bundles_dir(&app_handle)?.join(target_dir);
+
+        std::fs::create_dir_all(&dst)?;
+        match extract_archive(&mut archive, &dst) {
+            Ok(_) => Ok(dst.to_string_lossy().into_owned()),
+            Err(err) => {
+                std::fs::remove_dir_all(&dst)?;
+                Err(err.into())
+            }
+        }
+    })
+    .await
+}
+
+/// Parses a compression name. Every codec is built in, so any known one
+/// can be written.
+fn encodable_compression(name: &str) -> Result<Compression, String> {
+    name.parse::<Compression>().map_err(|err| err.to_string())
+}
+
+/// Like `encodable_compression`, but "keep" maps to `None` to leave each
+/// block's compression as it is.
+fn parse_target_compression(name: &str) -> Result<Option<Compression>, String> {
+    if name.eq_ignore_ascii_case("keep") {
+        return Ok(None);
+    }
+    encodable_compression(name).map(Some)
+}
+
+#[tauri::command]
+fn get_transcode_defaults(app: AppHandle) -> Result<TranscodeDefaults, CommandError> {
+    catch_panics(|| Ok(settings::load_transcode_defaults(&app)))
+}
+
+#[tauri::command]
+fn set_transcode_defaults(app: AppHandle, defaults: TranscodeDefaults) -> Result<(), CommandError> {
+    catch_panics(|| settings::save_transcode_defaults(&app, &defaults).map_err(CommandError::from))
+}
+
+const TRANSCODE_PROGRESS_EVENT: &str = "bundle://transcode-progress";
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranscodeProgress {
+    job_id: Option<String>,
+    stage: TranscodeStage,
+    done: u64,
+    total: u64,
+}
+
+/// Emits `bundle://transcode-progress` whenever a stage moves on by a
+/// whole percent, rather than for every block.
+fn transcode_progress_reporter(
+    app: AppHandle,
+    job_id: Option<String>,
+) -> impl FnMut(TranscodeStage, u64, u64) + Send + 'static {
+    // Stages report in turn, so each keeps its own last percent
+    let mut last_percent = HashMap::new();
+    move |stage, done, total| {
+        let percent = done * 100 / total.max(1);
+        if last_percent.insert(stage, percent) == Some(percent) {
+            return;
+        }
+        let _ = app.emit(
+            TRANSCODE_PROGRESS_EVENT,
+            TranscodeProgress {
+                job_id: job_id.clone(),
+                stage,
+                done,
+                total,
+            },
+        );
+    }
+}
+
+/// Progress is reported through `bundle://transcode-progress` events,
+/// tagged with `job_id` when one is given. With a `job_id` the transcode can
+/// be stopped through `cancel_transcode`, which removes the output.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn transcode_bundle(
+    app: AppHandle,
+    registry: State<'_, JobRegistry>,
+    path: String,
+    output: String,
+    target_compression: Option<String>,
+    reproducible: Option<bool>,
+    block_info_compression: Option<String>,
+    platform: Option<String>,
+    lzma_preset: Option<u32>,
+    zstd_level: Option<i32>,
+    lzma_dict_size: Option<u32>,
+    verify: Option<bool>,
+    block_size: Option<u32>,
+    lzma_lc: Option<u32>,
+    lzma_lp: Option<u32>,
+    lzma_pb: Option<u32>,
+    lz4hc_level: Option<i32>,
+    job_id: Option<String>,
+) -> Result<(), CommandError> {
+    let defaults = settings::load_transcode_defaults(&app);
+    let lzma_preset = lzma_preset.unwrap_or(defaults.lzma_preset);
+    let block_size = block_size.unwrap_or(defaults.block_size);
+    settings::validate_block_size(block_size)?;
+    let cancel = CancellationToken::new();
+    let _registration = job_id
+        .clone()
+        .map(|id| registry.register(id, cancel.clone()))
+        .transpose()?;
+    let progress = transcode_progress_reporter(app.clone(), job_id);
+    let platform = platform
+        .as_deref()
+        .unwrap_or(&defaults.platform)
+        .parse::<Platform>()?;
+    let compression = parse_target_compression(
+        target_compression
+            .as_deref()
+            .unwrap_or(&defaults.target_compression),
+    )?;
+    let block_info_compression = block_info_compression
+        .as_deref()
+        .map(encodable_compression)
+        .transpose()?;
+
+    run_blocking(move || {
+        let input_file = File::open(&path)?;
+        let reader = BufReader::new(input_file);
+        let decoder = AssetBundleDecoder::new(reader);
+
+        let output_file = File::create(&output)?;
+        let writer = std::io::BufWriter::new(output_file);
+        let encoder = AssetBundleEncoder::new(writer)
+            .reproducible(reproducible.unwrap_or(false))
+            .platform(Some(platform))
+            .lzma_preset(lzma_preset)
+            .lzma_dict_size(lzma_dict_size)
+            .lzma_bits(lzma_lc, lzma_lp, lzma_pb)
+            .zstd_level(zstd_level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))
+            .lz4hc_level(lz4hc_level.unwrap_or(LZ4HC_DEFAULT_LEVEL))
+            .block_size(Some(block_size))
+            .progress(progress)
+            .cancel(Some(cancel));
+        // Block by block, so a large bundle is never held in memory whole
+        let mut result = encoder
+            .transcode(decoder, compression, block_info_compression)
+            .map_err(CommandError::from);
+        // Read the output back, so a bundle that decodes wrong is caught
+        // before it can be uploaded
+        if result.is_ok() && verify.unwrap_or(false) {
+            // The block size may have split the data differently
+            let compare = AssetBundle::first_content_difference;
+            result = match roundtrip_difference(&path, &output, compare) {
+                Ok(None) => Ok(()),
+                Ok(Some(difference)) => Err(CommandError::Bundle(format!(
+                    "Transcoded bundle failed verification: {} is {} instead of {}",
+                    difference.field, difference.other, difference.original
+                ))),
+                Err(err) => Err(CommandError::Bundle(format!(
+                    "Transcoded bundle failed verification: {}",
+                    err
+                ))),
+            };
+        }
+        if result.is_err() {
+            // Don't leave an empty, partial or corrupt bundle behind
+            let _ = std::fs::remove_file(&output);
+        }
+        result
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TranscodeEstimate {
+    uncompressed_size: u64,
+    estimated_compressed_size: u64,
+    within_limits: bool,
+}
+
+/// Measures what `transcode_bundle` would produce by compressing for real
+/// and discarding the output, so an oversized bundle can be caught before a
+/// slow transcode. Options left out fall back to the stored transcode
+/// defaults, as they do for `transcode_bundle`.
+#[tauri::command]
+async fn estimate_transcode(
+    app: AppHandle,
+    path: String,
+    target_compression: Option<String>,
+    platform: Option<String>,
+) -> Result<TranscodeEstimate, CommandError> {
+    let defaults = settings::load_transcode_defaults(&app);
+    settings::validate_block_size(defaults.block_size)?;
+    let compression = parse_target_compression(
+        target_compression
+            .as_deref()
+            .unwrap_or(&defaults.target_compression),
+    )?;
+    let platform = platform
+        .as_deref()
+        .unwrap_or(&defaults.platform)
+        .parse::<Platform>()?;
+
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let uncompressed_size = AssetBundleDecoder::new(BufReader::new(file))
+            .decode_metadata()?
+            .info()
+            .uncompressed_size;
+
+        let file = File::open(&path)?;
+        let mut output = CountingWriter::new(std::io::sink());
+        // No platform on the encoder: an oversized bundle is reported
+        // through `within_limits` rather than as an error
+        AssetBundleEncoder::new(&mut output)
+            .lzma_preset(defaults.lzma_preset)
+            .block_size(Some(defaults.block_size))
+            .transcode(
+                AssetBundleDecoder::new(BufReader::new(file)),
+                compression,
+                None,
+            )?;
+        let estimated_compressed_size = output.written();
+
+        Ok(TranscodeEstimate {
+            uncompressed_size,
+            estimated_compressed_size,
+            within_limits: uncompressed_size <= platform.uncompressed_size_limit()
+                && estimated_compressed_size <= platform.compressed_size_limit(),
+        })
+    })
+    .await
+}
+
+/// Decodes base64 bundle bytes passed from the frontend, refusing input
+/// larger than any platform accepts.
+fn decode_bundle_base64(bytes: &str) -> Result<Vec<u8>, String> {
+    let limit = Platform::Pc.compressed_size_limit();
+    if bytes.len() as u64 > limit.div_ceil(3) * 4 {
+        return Err(format!("Bundle is larger than {} bytes", limit));
+    }
+    STANDARD.decode(bytes).map_err(|err| err.to_string())
+}
+
+/// In-memory counterpart of `transcode_bundle` for bundles the frontend
+/// already holds, taking and returning base64 bytes. The encoder options
+/// and their defaults are the same.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn transcode_bundle_bytes(
+    app: AppHandle,
+    bytes: String,
+    target_compression: Option<String>,
+    platform: Option<String>,
+    lzma_preset: Option<u32>,
+    zstd_level: Option<i32>,
+    lzma_dict_size: Option<u32>,
+    block_size: Option<u32>,
+    lzma_lc: Option<u32>,
+    lzma_lp: Option<u32>,
+    lzma_pb: Option<u32>,
+    lz4hc_level: Option<i32>,
+) -> Result<String, CommandError> {
+    let defaults = settings::load_transcode_defaults(&app);
+    let lzma_preset = lzma_preset.unwrap_or(defaults.lzma_preset);
+    let block_size = block_size.unwrap_or(defaults.block_size);
+    settings::validate_block_size(block_size)?;
+    let platform = platform
+        .as_deref()
+        .unwrap_or(&defaults.platform)
+        .parse::<Platform>()?;
+    let compression = parse_target_compression(
+        target_compression
+            .as_deref()
+            .unwrap_or(&defaults.target_compression),
+    )?;
+
+    run_blocking(move || {
+        transcode_base64(&bytes, compression, |encoder| {
+            encoder
+                .platform(Some(platform))
+                .lzma_preset(lzma_preset)
+                .lzma_dict_size(lzma_dict_size)
+                .lzma_bits(lzma_lc, lzma_lp, lzma_pb)
+                .zstd_level(zstd_level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL))
+                .lz4hc_level(lz4hc_level.unwrap_or(LZ4HC_DEFAULT_LEVEL))
+                .block_size(Some(block_size))
+        })
+    })
+    .await
+}
+
+type VecEncoder<'a> = AssetBundleEncoder<&'a mut Vec<u8>>;
+
+fn transcode_base64(
+    bytes: &str,
+    compression: Option<Compression>,
+    configure: impl FnOnce(VecEncoder) -> VecEncoder,
+) -> Result<String, CommandError> {
+    let input = decode_bundle_base64(bytes)?;
+    let mut output = Vec::new();
+    configure(AssetBundleEncoder::new(&mut output)).transcode(
+        AssetBundleDecoder::new(Cursor::new(input)),
+        compression,
+        None,
+    )?;
+    Ok(STANDARD.encode(output))
+}
+
+#[tauri::command]
+async fn bundle_info_bytes(bytes: String) -> Result<BundleInfo, CommandError> {
+    run_blocking(move || {
+        let input = decode_bundle_base64(&bytes)?;
+        AssetBundleDecoder::new(Cursor::new(input))
+            .decode_metadata()
+            .map(|bundle| bundle.info())
+            .map_err(CommandError::from)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn list_entries(bundle_path: String) -> Result<Vec<String>, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&bundle_path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode_metadata()?;
+        Ok(bundle
+            .directory_info()
+            .iter()
+            .map(|entry| entry.path.clone())
+            .collect())
+    })
+    .await
+}
+
+/// Writes the decompressed bytes of the directory entry `entry_path` to
+/// `output`.
+#[tauri::command]
+async fn extract_entry(
+    bundle_path: String,
+    entry_path: String,
+    output: String,
+) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let file = File::open(&bundle_path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+        let entry = bundle.find_entry(&entry_path)?;
+        let data = bundle
+            .entry_data(entry)
+            .ok_or_else(|| format!("Entry {} lies outside the decompressed data", entry.path))?;
+        std::fs::write(&output, data).map_err(CommandError::from)
+    })
+    .await
+}
+
+/// Writes a copy of the bundle with the directory entry `entry_path`
+/// replaced by the contents of `input`, e.g. to patch a thumbnail without
+/// rebuilding the bundle in Unity. Blocks keep their compression.
+#[tauri::command]
+async fn replace_entry(
+    app: AppHandle,
+    bundle_path: String,
+    entry_path: String,
+    input: String,
+    output: String,
+    platform: Option<String>,
+) -> Result<(), CommandError> {
+    let defaults = settings::load_transcode_defaults(&app);
+    let platform = platform
+        .as_deref()
+        .unwrap_or(&defaults.platform)
+        .parse::<Platform>()?;
+
+    run_blocking(move || {
+        let file = File::open(&bundle_path)?;
+        let mut bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+        bundle.replace_entry(&entry_path, std::fs::read(&input)?)?;
+
+        let writer = std::io::BufWriter::new(File::create(&output)?);
+        let result = AssetBundleEncoder::new(writer)
+            .platform(Some(platform))
+            .lzma_preset(defaults.lzma_preset)
+            .block_size(Some(defaults.block_size))
+            .encode(&bundle);
+        if let Err(err) = result {
+            // Don't leave an empty or partial bundle behind
+            let _ = std::fs::remove_file(&output);
+            return Err(err.into());
+        }
+        Ok(())
+    })
+    .await
+}
+
+#[tauri::command]
+async fn bundle_asset_names(path: String) -> Result<Vec<(String, i64)>, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let decoder = AssetBundleDecoder::new(BufReader::new(file));
+        let bundle = decoder.decode()?;
+
+        let mut names = Vec::new();
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            // Files in a format we can't parse contribute no names
+            let Ok(serialized) = SerializedFile::parse(&data) else {
+                continue;
+            };
+            names.extend(serialized.objects.iter().filter_map(|object| {
+                serialized
+                    .object_name(object)
+                    .map(|name| (name, object.path_id))
+            }));
+        }
+        Ok(names)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectTypeCount {
+    class_id: i32,
+    /// `None` when the file was built without type trees.
+    type_name: Option<String>,
+    count: u64,
+    total_size: u64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ObjectTypes {
+    types: Vec<ObjectTypeCount>,
+    /// Paths of the SerializedFiles in a format we can't parse, whose
+    /// objects aren't counted.
+    skipped: Vec<String>,
+}
+
+/// Counts the objects of each type across the bundle's SerializedFiles,
+/// largest total size first. Like `detect_platform`, files that can't be
+/// parsed are skipped rather than failing the whole bundle.
+#[tauri::command]
+async fn bundle_object_types(path: String) -> Result<ObjectTypes, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+
+        let mut counts: Vec<ObjectTypeCount> = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            let Ok(serialized) = SerializedFile::parse(&data) else {
+                skipped.push(entry.path.clone());
+                continue;
+            };
+            for object in &serialized.objects {
+                let type_name = serialized.type_name(object);
+                let index = match counts.iter().position(|count| {
+                    count.class_id == object.class_id && count.type_name.as_deref() == type_name
+                }) {
+                    Some(index) => index,
+                    None => {
+                        counts.push(ObjectTypeCount {
+                            class_id: object.class_id,
+                            type_name: type_name.map(str::to_owned),
+                            count: 0,
+                            total_size: 0,
+                        });
+                        counts.len() - 1
+                    }
+                };
+                counts[index].count += 1;
+                counts[index].total_size += object.byte_size as u64;
+            }
+        }
+        counts.sort_by(|a, b| b.total_size.cmp(&a.total_size));
+        Ok(ObjectTypes {
+            types: counts,
+            skipped,
+        })
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BundleTextures {
+    textures: Vec<TextureInfo>,
+    /// Paths of the SerializedFiles in a format we can't parse, whose
+    /// textures aren't listed.
+    skipped: Vec<String>,
+}
+
+/// Lists every Texture2D in the bundle with its size, format and estimated
+/// GPU memory, largest first, to show what is inflating a bundle. Files that
+/// can't be parsed are skipped, as in `bundle_object_types`.
+#[tauri::command]
+async fn bundle_textures(path: String) -> Result<BundleTextures, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+
+        let mut textures = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            let Ok(serialized) = SerializedFile::parse(&data) else {
+                skipped.push(entry.path.clone());
+                continue;
+            };
+            textures.extend(assets::textures(&serialized));
+        }
+        textures.sort_by(|a, b| b.vram_size.cmp(&a.vram_size));
+        Ok(BundleTextures { textures, skipped })
+    })
+    .await
+}
+
+/// Counts the triangles, meshes, renderers and bones in the bundle, the
+/// inputs to VRChat's performance rank. Files that can't be parsed are
+/// skipped, as in `bundle_object_types`.
+#[tauri::command]
+async fn bundle_mesh_stats(path: String) -> Result<MeshStats, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+
+        let mut stats = MeshStats::default();
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            match SerializedFile::parse(&data) {
+                Ok(serialized) => stats.add(&serialized),
+                Err(_) => stats.skipped.push(entry.path.clone()),
+            }
+        }
+        stats
+            .meshes
+            .sort_by(|a, b| b.triangle_count.cmp(&a.triangle_count));
+        Ok(stats)
+    })
+    .await
+}
+
+/// Lists the bundle's shaders, largest first, with how many materials use
+/// each, to catch heavy shaders that were included by accident. Files that
+/// can't be parsed are skipped, as in `bundle_object_types`.
+#[tauri::command]
+async fn bundle_shaders(path: String) -> Result<MaterialStats, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+
+        let mut stats = MaterialStats::default();
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            match SerializedFile::parse(&data) {
+                Ok(serialized) => stats.add(&serialized),
+                Err(_) => stats.skipped.push(entry.path.clone()),
+            }
+        }
+        stats.shaders.sort_by(|a, b| b.size.cmp(&a.size));
+        Ok(stats)
+    })
+    .await
+}
+
+/// Reads the build target of the bundle's first SerializedFile, so the
+/// frontend can pick size limits and an upload slot without asking. Returns
+/// `"pc"`, `"android"`, `"ios"`, or `None` when the target isn't one of those
+/// or the bundle has no file we can parse.
+#[tauri::command]
+async fn detect_platform(path: String) -> Result<Option<String>, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode()?;
+
+        for entry in bundle.directory_info() {
+            let Some(data) = bundle
+                .entry_data(entry)
+                .filter(|_| entry.is_serialized_file())
+            else {
+                continue;
+            };
+            if let Ok(serialized) = SerializedFile::parse(&data) {
+                return Ok(serialized.platform_name().map(str::to_owned));
+            }
+        }
+        Ok(None)
+    })
+    .await
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DirectoryEntry<'a> {
+    path: &'a str,
+    size: u64,
+    offset: u64,
+    flags: u32,
+}
+
+#[tauri::command]
+async fn export_directory_info(
+    path: String,
+    output: String,
+    format: String,
+) -> Result<(), CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let bundle = AssetBundleDecoder::new(BufReader::new(file)).decode_metadata()?;
+        let entries: Vec<DirectoryEntry> = bundle
+            .directory_info()
+            .iter()
+            .map(|entry| DirectoryEntry {
+                path: &entry.path,
+                size: entry.size(),
+                offset: entry.offset(),
+                flags: entry.flags(),
+            })
+            .collect();
+
+        let contents = match format.to_ascii_lowercase().as_str() {
+            "json" => serde_json::to_string_pretty(&entries).map_err(|err| err.to_string())?,
+            "csv" => {
+                let mut csv = String::from("path,size,offset,flags\r\n");
+                for entry in &entries {
+                    csv.push_str(&format!(
+                        "{},{},{},{}\r\n",
+                        csv_field(entry.path),
+                        entry.size,
+                        entry.offset,
+                        entry.flags
+                    ));
+                }
+                csv
+            }
+            other => return Err(format!("Unknown export format: {}", other).into()),
+        };
+        std::fs::write(&output, contents).map_err(CommandError::from)
+    })
+    .await
+}
+
+/// Summarizes a bundle's header, blocks and directory without
+/// decompressing any block data.
+#[tauri::command]
+async fn bundle_info(path: String) -> Result<BundleInfo, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        AssetBundleDecoder::new(BufReader::new(file))
+            .decode_metadata()
+            .map(|bundle| bundle.info())
+            .map_err(CommandError::from)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn inspect_bundle_from_url(url: String) -> Result<BundleInfo, CommandError> {
+    remote::fetch_bundle_info(&url).await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockSpeed {
+    index: usize,
+    compression: Option<&'static str>,
+    compressed_size: u32,
+    uncompressed_size: u32,
+    millis: f64,
+    mb_per_sec: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CompressionSpeed {
+    compression: Option<&'static str>,
+    uncompressed_size: u64,
+    millis: f64,
+    mb_per_sec: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct DecompressSpeed {
+    blocks: Vec<BlockSpeed>,
+    by_compression: Vec<CompressionSpeed>,
+}
+
+fn mb_per_sec(bytes: u64, seconds: f64) -> f64 {
+    bytes as f64 / 1_000_000.0 / seconds.max(1e-9)
+}
+
+#[tauri::command]
+async fn measure_decompression_speed(path: String) -> Result<DecompressSpeed, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let timings = AssetBundleDecoder::new(BufReader::new(file)).time_blocks()?;
+
+        let blocks = timings
+            .iter()
+            .map(|timing| BlockSpeed {
+                index: timing.index,
+                compression: timing.compression.map(Compression::name),
+                compressed_size: timing.compressed_size,
+                uncompressed_size: timing.uncompressed_size,
+                millis: timing.elapsed.as_secs_f64() * 1000.0,
+                mb_per_sec: mb_per_sec(
+                    timing.uncompressed_size.into(),
+                    timing.elapsed.as_secs_f64(),
+                ),
+            })
+            .collect();
+
+        let mut by_compression: Vec<CompressionSpeed> = Vec::new();
+        for timing in &timings {
+            let compression = timing.compression.map(Compression::name);
+            let index = match by_compression
+                .iter()
+                .position(|speed| speed.compression == compression)
+            {
+                Some(index) => index,
+                None => {
+                    by_compression.push(CompressionSpeed {
+                        compression,
+                        uncompressed_size: 0,
+                        millis: 0.0,
+                        mb_per_sec: 0.0,
+                    });
+                    by_compression.len() - 1
+                }
+            };
+            let speed = &mut by_compression[index];
+            speed.uncompressed_size += u64::from(timing.uncompressed_size);
+            speed.millis += timing.elapsed.as_secs_f64() * 1000.0;
+            speed.mb_per_sec = mb_per_sec(speed.uncompressed_size, speed.millis / 1000.0);
+        }
+
+        Ok(DecompressSpeed {
+            blocks,
+            by_compression,
+        })
+    })
+    .await
+}
+
+#[tauri::command]
+async fn decompress_block(path: String, block_index: usize) -> Result<Vec<u8>, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let decoder = AssetBundleDecoder::new(BufReader::new(file));
+        decoder
+            .decode_block(block_index)
+            .map_err(CommandError::from)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct RoundtripCheck {
+    matches: bool,
+    difference: Option<BundleDifference>,
+}
+
+/// Decodes both bundles and returns the first difference `compare` finds.
+fn roundtrip_difference(
+    original: &str,
+    transcoded: &str,
+    compare: fn(&AssetBundle, &AssetBundle) -> Option<BundleDifference>,
+) -> Result<Option<BundleDifference>, CommandError> {
+    let decode = |path: &str| -> Result<AssetBundle, CommandError> {
+        let file = File::open(path)?;
+        Ok(AssetBundleDecoder::new(BufReader::new(file)).decode()?)
+    };
+    let mut original = decode(original)?;
+    // Encoding turns UnityRaw and UnityWeb into UnityFS, so a legacy
+    // original is compared in the form it was written in
+    original.convert_to_unity_fs();
+    Ok(compare(&original, &decode(transcoded)?))
+}
+
+/// Decodes `original` and `transcoded` and compares their content, so a
+/// transcoded bundle can be checked before it is uploaded. The data may be
+/// split into blocks differently, as after transcoding with a block size,
+/// unless `compare_blocks` asks for the same block layout as well.
+#[tauri::command]
+async fn verify_roundtrip(
+    original: String,
+    transcoded: String,
+    compare_blocks: Option<bool>,
+) -> Result<RoundtripCheck, CommandError> {
+    let compare = if compare_blocks.unwrap_or(false) {
+        AssetBundle::first_difference
+    } else {
+        AssetBundle::first_content_difference
+    };
+    run_blocking(move || {
+        let difference = roundtrip_difference(&original, &transcoded, compare)?;
+        Ok(RoundtripCheck {
+            matches: difference.is_none(),
+            difference,
+        })
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct TruncationResult {
+    offset: u64,
+    /// "ok", "error" or "panic"
+    outcome: &'static str,
+    message: Option<String>,
+}
+
+/// Self-test that decodes the bundle cut off at a range of offsets and
+/// records whether each truncation fails cleanly. Diagnostic only.
+#[tauri::command]
+async fn fuzz_truncate_decode(path: String) -> Result<Vec<TruncationResult>, CommandError> {
+    run_blocking(move || {
+        let data = std::fs::read(&path)?;
+
+        // Every offset through the header and block info, then a sample of
+        // the data region
+        let mut offsets: Vec<usize> = (0..data.len().min(256)).collect();
+        let step = (data.len() / 32).max(1);
+        offsets.extend((256..data.len()).step_by(step));
+
+        let results = offsets
+            .into_iter()
+            .map(|offset| {
+                let truncated = &data[..offset];
+                let outcome = std::panic::catch_unwind(|| {
+                    AssetBundleDecoder::new(std::io::Cursor::new(truncated)).decode()
+                });
+                let (outcome, message) = match outcome {
+                    Ok(Ok(_)) => ("ok", None),
+                    Ok(Err(err)) => ("error", Some(err.to_string())),
+                    Err(panic) => ("panic", Some(panic_message(&*panic))),
+                };
+                TruncationResult {
+                    offset: offset as u64,
+                    outcome,
+                    message,
+                }
+            })
+            .collect();
+        Ok(results)
+    })
+    .await
+}
+
+#[tauri::command]
+async fn bundle_padding_report(path: String) -> Result<Vec<PaddingRegion>, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        AssetBundleDecoder::new(BufReader::new(file))
+            .padding_regions()
+            .map_err(CommandError::from)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SizeCheck {
+    declared: u64,
+    actual: u64,
+    difference: i64,
+    matches: bool,
+}
+
+/// `a - b`, saturating at the ends of `i64` rather than overflowing.
+fn saturating_difference(a: u64, b: u64) -> i64 {
+    (i128::from(a) - i128::from(b)).clamp(i64::MIN.into(), i64::MAX.into()) as i64
+}
+
+fn size_check(path: &str) -> Result<SizeCheck, CommandError> {
+    let file = File::open(path)?;
+    let actual = file.metadata()?.len();
+    let field = AssetBundleDecoder::new(BufReader::new(file)).read_size_field()?;
+    Ok(SizeCheck {
+        declared: field.declared,
+        actual,
+        difference: saturating_difference(actual, field.declared),
+        matches: field.declared == actual,
+    })
+}
+
+#[tauri::command]
+async fn check_bundle_size_field(path: String) -> Result<SizeCheck, CommandError> {
+    run_blocking(move || size_check(&path)).await
+}
+
+#[tauri::command]
+async fn fix_bundle_size_field(path: String, output: String) -> Result<SizeCheck, CommandError> {
+    run_blocking(move || {
+        let check = size_check(&path)?;
+        if path != output {
+            std::fs::copy(&path, &output)?;
+        }
+        if check.matches {
+            return Ok(check);
+        }
+
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&output)?;
+        let field = AssetBundleDecoder::new(&mut file).read_size_field()?;
+        file.seek(SeekFrom::Start(field.offset))?;
+        file.write_all(&check.actual.to_be_bytes())?;
+        file.sync_all()?;
+        Ok(check)
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ValidationReport {
+    passed: bool,
+    checks: Vec<ValidationCheck>,
+}
+
+/// Checks the bundle's size field, then decompresses every block and checks
+/// the directory against the result, so a damaged bundle is caught before
+/// it is uploaded.
+#[tauri::command]
+async fn validate_bundle(path: String) -> Result<ValidationReport, CommandError> {
+    run_blocking(move || {
+        let mut checks = Vec::new();
+        let (passed, detail) = match size_check(&path) {
+            Ok(check) if check.matches => (true, None),
+            Ok(check) => (
+                false,
+                Some(format!(
+                    "Header declares {} bytes but the file is {} bytes",
+                    check.declared, check.actual
+                )),
+            ),
+            Err(err) => (false, Some(err.to_string())),
+        };
+        checks.push(ValidationCheck {
+            name: "Size field".to_owned(),
+            passed,
+            detail,
+        });
+
+        let file = File::open(&path)?;
+        match AssetBundleDecoder::new(BufReader::new(file)).validate() {
+            Ok(bundle_checks) => checks.extend(bundle_checks),
+            Err(err) => checks.push(ValidationCheck {
+                name: "Metadata".to_owned(),
+                passed: false,
+                detail: Some(err.to_string()),
+            }),
+        }
+        Ok(ValidationReport {
+            passed: checks.iter().all(|check| check.passed),
+            checks,
+        })
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UnityVersionCheck {
+    unity_version: String,
+    unity_revision: String,
+    expected: String,
+    matches: bool,
+    /// `"stripped"` when the bundle was built without its Unity version,
+    /// `"release"` when the year or minor version differs, and `"patch"`
+    /// when only the patch or build suffix does.
+    mismatch: Option<&'static str>,
+}
+
+/// Year and minor version of a Unity version like `2022.3.22f1`.
+fn unity_release(version: &str) -> (&str, &str) {
+    let mut parts = version.split('.');
+    (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    )
+}
+
+/// Compares the Unity version a bundle was built with against `expected`,
+/// or the stored `unity_version` setting.
+#[tauri::command]
+async fn check_unity_version(
+    app: AppHandle,
+    path: String,
+    expected: Option<String>,
+) -> Result<UnityVersionCheck, CommandError> {
+    let expected =
+        expected.unwrap_or_else(|| settings::load_transcode_defaults(&app).unity_version);
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let info = AssetBundleDecoder::new(BufReader::new(file))
+            .decode_metadata()?
+            .info();
+
+        let mismatch = if info.unity_revision == expected {
+            None
+        } else if info.unity_revision.starts_with("0.0.0") {
+            Some("stripped")
+        } else if unity_release(&info.unity_revision) != unity_release(&expected) {
+            Some("release")
+        } else {
+            Some("patch")
+        };
+        Ok(UnityVersionCheck {
+            unity_version: info.unity_version,
+            unity_revision: info.unity_revision,
+            expected,
+            matches: mismatch.is_none(),
+            mismatch,
+        })
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SizeLimitCheck {
+    limit: u64,
+    compressed_size: u64,
+    uncompressed_size: u64,
+    /// Bytes left under the limit for the file as stored, negative when over
+    /// and saturated at the ends of `i64`.
+    margin: i64,
+    passes: bool,
+}
+
+/// Checks a bundle against a caller-chosen size limit, reading only its
+/// metadata.
+#[tauri::command]
+async fn check_custom_size_limit(
+    path: String,
+    limit_bytes: u64,
+) -> Result<SizeLimitCheck, CommandError> {
+    run_blocking(move || {
+        let file = File::open(&path)?;
+        let compressed_size = file.metadata()?.len();
+        let meta = AssetBundleDecoder::new(BufReader::new(file))
+            .decode_metadata()?
+            .info();
+        let uncompressed_size = meta
+            .blocks
+            .iter()
+            .map(|block| u64::from(block.uncompressed_size))
+            .sum();
+        Ok(SizeLimitCheck {
+            limit: limit_bytes,
+            compressed_size,
+            uncompressed_size,
+            margin: saturating_difference(limit_bytes, compressed_size),
+            passes: compressed_size <= limit_bytes,
+        })
+    })
+    .await
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Capabilities {
+    version: &'static str,
+    decode_compression: Vec<&'static str>,
+    encode_compression: Vec<&'static str>,
+    /// Whether a keychain backend is built for this platform. Whether it is
+    /// usable, e.g. unlocked, is only known once `keyring_available` asks.
+    keyring: bool,
+    updater: bool,
+}
+
+/// Reports what this build supports without touching the OS keychain, so
+/// calling it at startup can't bring up a keychain prompt.
+#[tauri::command]
+fn capabilities() -> Result<Capabilities, CommandError> {
+    catch_panics(probe_capabilities)
+}
+
+fn probe_capabilities() -> Result<Capabilities, CommandError> {
+    // Every codec is an unconditional dependency, so both lists are the
+    // full set
+    let codecs: Vec<_> = Compression::ALL.iter().map(|c| c.name()).collect();
+    Ok(Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        decode_compression: codecs.clone(),
+        encode_compression: codecs,
+        keyring: cfg!(any(
+            target_os = "macos",
+            target_os = "ios",
+            target_os = "windows",
+            target_os = "linux",
+            target_os = "freebsd",
+            target_os = "openbsd"
+        )),
+        updater: cfg!(not(any(target_os = "android", target_os = "ios"))),
+    })
+}
+
+/// Checks that the OS keychain can be read, by looking up an entry that is
+/// never written. This may bring up an unlock prompt, so call it when a
+/// token is about to be saved or loaded rather than at startup.
+#[tauri::command]
+async fn keyring_available() -> Result<bool, CommandError> {
+    run_blocking(|| {
+        let entry = Entry::new("third_vrchat_token", "__capabilities_probe__")?;
+        Ok(matches!(
+            entry.get_password(),
+            Ok(_) | Err(keyring::Error::NoEntry)
+        ))
+    })
+    .await
+}
+
+const USER_AGENT: &str = "Third Uploader/1.0.0 contact@third3d.com";
+
+/// Recognizes a server refusing a chunked body because it needs the length
+/// up front, as S3 presigned PUTs do.
+fn chunked_rejection(status: StatusCode, body: &str) -> Option<String> {
+    let rejected = status == StatusCode::LENGTH_REQUIRED
+        || (status == StatusCode::NOT_IMPLEMENTED && body.contains("Transfer-Encoding"))
+        || body.contains("MissingContentLength");
+    rejected.then(|| {
+        format!(
+            "{}: server requires a Content-Length and does not accept chunked uploads",
+            status.as_str()
+        )
+    })
+}
+
+const UPLOAD_PROGRESS_EVENT: &str = "upload://progress";
+
+/// Cancellation tokens of the running uploads and transcodes that were
+/// given an id.
+#[derive(Default)]
+struct JobRegistry(Mutex<HashMap<String, CancellationToken>>);
+
+impl JobRegistry {
+    fn register(&self, id: String, token: CancellationToken) -> Result<Registration<'_>, String> {
+        let mut jobs = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        if jobs.contains_key(&id) {
+            return Err(format!("A job with id {} is already running", id));
+        }
+        jobs.insert(id.clone(), token);
+        Ok(Registration { registry: self, id })
+    }
+
+    fn cancel(&self, id: &str) -> bool {
+        let jobs = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        match jobs.get(id) {
+            Some(token) => {
+                token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Removes a job from the registry once it ends, however it ends.
+struct Registration<'a> {
+    registry: &'a JobRegistry,
+    id: String,
+}
+
+impl Drop for Registration<'_> {
+    fn drop(&mut self) {
+        self.registry
+            .0
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .remove(&self.id);
+    }
+}
+
+#[derive(Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadProgress {
+    upload_id: Option<String>,
+    url: String,
+    path: String,
+    uploaded: u64,
+    /// `None` for chunked uploads of unknown length.
+    total: Option<u64>,
+    /// Average since the current attempt started.
+    bytes_per_sec: u64,
+    done: bool,
+    error: Option<String>,
+}
+
+/// Emits `upload://progress` events for one upload. The terminal event is
+/// sent on drop, so failed and cancelled uploads still end with one.
+struct UploadProgressReporter {
+    app: AppHandle,
+    upload_id: Option<String>,
+    url: String,
+    path: String,
+    total: Option<u64>,
+    uploaded: AtomicU64,
+    started: Mutex<Instant>,
+    finished: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+impl UploadProgressReporter {
+    fn new(
+        app: AppHandle,
+        upload_id: Option<String>,
+        url: String,
+        path: String,
+        total: Option<u64>,
+    ) -> Self {
+        Self {
+            app,
+            upload_id,
+            url,
+            path,
+            total,
+            uploaded: AtomicU64::new(0),
+            started: Mutex::new(Instant::now()),
+            finished: AtomicBool::new(false),
+            error: Mutex::new(None),
+        }
+    }
+
+    fn emit(&self, done: bool, error: Option<String>) {
+        let uploaded = self.uploaded.load(Ordering::Relaxed);
+        let elapsed = self
+            .started
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .elapsed();
+        let _ = self.app.emit(
+            UPLOAD_PROGRESS_EVENT,
+            UploadProgress {
+                upload_id: self.upload_id.clone(),
+                url: self.url.clone(),
+                path: self.path.clone(),
+                uploaded,
+                total: self.total,
+                bytes_per_sec: (uploaded as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+                done,
+                error,
+            },
+        );
+    }
+
+    /// Starts counting from zero again for a retried attempt.
+    fn restart(&self) {
+        self.uploaded.store(0, Ordering::Relaxed);
+        *self.started.lock().unwrap_or_else(|err| err.into_inner()) = Instant::now();
+        self.emit(false, None);
+    }
+
+    /// Records `len` more bytes handed to the HTTP client.
+    fn advance(&self, len: usize) {
+        self.uploaded.fetch_add(len as u64, Ordering::Relaxed);
+        self.emit(false, None);
+    }
+
+    fn finish(&self, result: &Result<Option<String>, CommandError>) {
+        match result {
+            Ok(_) => self.finished.store(true, Ordering::Relaxed),
+            Err(err) => {
+                *self.error.lock().unwrap_or_else(|err| err.into_inner()) = Some(err.to_string())
+            }
+        }
+    }
+}
+
+impl Drop for UploadProgressReporter {
+    fn drop(&mut self) {
+        let error = if self.finished.load(Ordering::Relaxed) {
+            None
+        } else {
+            let error = self.error.get_mut().unwrap_or_else(|err| err.into_inner());
+            Some(
+                error
+                    .take()
+                    .unwrap_or_else(|| CommandError::Cancelled("Upload").to_string()),
+            )
+        };
+        self.emit(true, error);
+    }
+}
+
+/// A failed upload attempt, and whether trying again may succeed.
+struct AttemptError {
+    error: CommandError,
+    retryable: bool,
+}
+
+impl AttemptError {
+    fn fatal(err: impl Into<CommandError>) -> Self {
+        Self {
+            error: err.into(),
+            retryable: false,
+        }
+    }
+
+    fn retryable(err: impl Into<CommandError>) -> Self {
+        Self {
+            error: err.into(),
+            retryable: true,
+        }
+    }
+}
+
+const RETRY_BASE_DELAY_MS: u64 = 500;
+const RETRY_MAX_DELAY_MS: u64 = 30_000;
+
+/// Exponential backoff for the given retry, randomized over its upper half
+/// so parallel part uploads don't retry in lockstep.
+fn retry_delay(retry: u32) -> std::time::Duration {
+    let delay = RETRY_BASE_DELAY_MS
+        .saturating_mul(1 << retry.min(16))
+        .min(RETRY_MAX_DELAY_MS);
+    let jitter = rand::thread_rng().gen_range(0..=delay / 2);
+    std::time::Duration::from_millis(delay / 2 + jitter)
+}
+
+/// Proxy for uploads, as set with `set_proxy`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ProxyConfig {
+    url: String,
+    username: Option<String>,
+    password: Option<String>,
+    /// Comma separated hosts reached directly, in the format of `NO_PROXY`.
+    no_proxy: Option<String>,
+}
+
+impl ProxyConfig {
+    /// Whether `host` matches a `no_proxy` entry, either exactly or as a
+    /// subdomain of it.
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host.to_ascii_lowercase();
+        self.no_proxy
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|entry| entry.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+    }
+}
+
+/// Client shared by the upload commands, so multipart uploads reuse
+/// connections instead of opening a new pool for every part. Managed
+/// behind a mutex so `set_proxy` can replace it.
+#[derive(Clone)]
+struct UploadClient {
+    client: reqwest::Client,
+    proxy: Option<Arc<ProxyConfig>>,
+}
+
+impl UploadClient {
+    fn new(proxy: Option<ProxyConfig>) -> Result<Self, String> {
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(15))
+            .pool_idle_timeout(std::time::Duration::from_secs(90))
+            .pool_max_idle_per_host(8);
+        if let Some(config) = &proxy {
+            let mut proxy = reqwest::Proxy::all(&config.url)
+                .map_err(|err| format!("Invalid proxy URL: {}", err))?;
+            if let Some(username) = &config.username {
+                proxy = proxy.basic_auth(username, config.password.as_deref().unwrap_or_default());
+            }
+            proxy = proxy.no_proxy(
+                config
+                    .no_proxy
+                    .as_deref()
+                    .and_then(reqwest::NoProxy::from_string),
+            );
+            builder = builder.proxy(proxy);
+        }
+        Ok(Self {
+            client: builder.build().map_err(|e| e.to_string())?,
+            proxy: proxy.map(Arc::new),
+        })
+    }
+
+    /// Reports a failed request to `url`, telling a proxy that can't be
+    /// reached apart from other network errors.
+    fn request_error(&self, url: &str, err: reqwest::Error) -> CommandError {
+        let proxied = match (&self.proxy, Url::parse(url)) {
+            (Some(proxy), Ok(url)) => !url.host_str().is_some_and(|host| proxy.bypasses(host)),
+            _ => false,
+        };
+        if proxied && err.is_connect() {
+            CommandError::Proxy(format!("Could not connect through the proxy: {}", err))
+        } else {
+            err.into()
+        }
+    }
+}
+
+/// Error for an unsuccessful response, singling out a proxy refusing the
+/// request.
+fn status_error(status: StatusCode, body: String) -> CommandError {
+    if status == StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        CommandError::Proxy(format!("{}: proxy authentication failed", status.as_str()))
+    } else {
+        CommandError::HttpStatus {
+            code: status.as_u16(),
+            body,
+        }
+    }
+}
+
+/// Routes uploads through `proxy`, or back to a direct connection when it
+/// is `None`. Uploads already running keep the client they started with.
+#[tauri::command]
+fn set_proxy(
+    client: State<'_, Mutex<UploadClient>>,
+    proxy: Option<ProxyConfig>,
+) -> Result<(), CommandError> {
+    catch_panics(|| {
+        let new_client = UploadClient::new(proxy)?;
+        *client.lock().unwrap_or_else(|err| err.into_inner()) = new_client;
+        Ok(())
+    })
+}
+
+fn upload_client(client: &Mutex<UploadClient>) -> UploadClient {
+    client.lock().unwrap_or_else(|err| err.into_inner()).clone()
+}
+
+/// The part of a file `put_file_range` sends, and how.
+struct FileRange<'a> {
+    url: &'a str,
+    path: &'a str,
+    start: u64,
+    length: u64,
+    chunked: bool,
+    /// Limit on the whole request, body transfer included.
+    timeout: Option<std::time::Duration>,
+    headers: &'a HeaderMap,
+    /// Hex MD5 of the range, checked against the response ETag.
+    expected_md5_hex: Option<&'a str>,
+    max_bytes_per_sec: Option<u64>,
+}
+
+/// Most a throttled upload may run ahead after the server stalled.
+const THROTTLE_MAX_BURST: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Paces an upload to an average rate, delaying each chunk until the bytes
+/// sent so far are due.
+struct Throttle {
+    bytes_per_sec: u64,
+    started: Instant,
+    sent: u64,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: Instant::now(),
+            sent: 0,
+        }
+    }
+
+    /// How long to hold back a chunk of `len` bytes.
+    fn delay(&mut self, len: usize) -> std::time::Duration {
+        self.sent += len as u64;
+        let due = std::time::Duration::from_secs_f64(self.sent as f64 / self.bytes_per_sec as f64);
+        // Time lost to a slow server is only made up for in short bursts
+        if let Some(slack) = self.started.elapsed().checked_sub(due + THROTTLE_MAX_BURST) {
+            self.started += slack;
+        }
+        due.saturating_sub(self.started.elapsed())
+    }
+}
+
+async fn put_file_range(
+    client: &UploadClient,
+    range: &FileRange<'_>,
+    reporter: Arc<UploadProgressReporter>,
+) -> Result<Option<String>, AttemptError> {
+    let FileRange {
+        url,
+        path,
+        start,
+        length,
+        chunked,
+        timeout,
+        headers,
+        expected_md5_hex,
+        max_bytes_per_sec,
+    } = *range;
+    let mut file = tokio::fs::File::open(path)
+        .await
+        .map_err(AttemptError::fatal)?;
+    file.seek(SeekFrom::Start(start))
+        .await
+        .map_err(AttemptError::fatal)?;
+    // Smaller chunks keep a low rate limit smooth
+    let chunk_size = max_bytes_per_sec.map_or(64 * 1024, |rate| (rate / 10).clamp(1024, 64 * 1024));
+    let mut throttle = max_bytes_per_sec.map(Throttle::new);
+    // Counted as reqwest polls the stream, not as the file is read
+    let stream = ReaderStream::with_capacity(
+        file.take(if chunked { u64::MAX } else { length }),
+        chunk_size as usize,
+    )
+    .then(move |chunk| {
+        let delay = match (&mut throttle, &chunk) {
+            (Some(throttle), Ok(data)) => throttle.delay(data.len()),
+            _ => std::time::Duration::ZERO,
+        };
+        async move {
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            chunk
+        }
+    })
+    .inspect_ok(move |chunk| reporter.advance(chunk.len()));
+
+    let mut request = client
+        .client
+        .put(url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .headers(headers.clone());
+    if !chunked {
+        request = request.header(CONTENT_LENGTH, length.to_string());
+    }
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+    let request = request.body(Body::wrap_stream(stream));
+
+    // Dropped connections and other transport errors are worth retrying
+    let response = request.send().await.map_err(|err| {
+        if err.is_timeout() {
+            AttemptError::retryable(format!(
+                "Timed out: upload did not finish within {} seconds",
+                timeout.unwrap_or_default().as_secs()
+            ))
+        } else {
+            AttemptError::retryable(client.request_error(url, err))
+        }
+    })?;
+    if response.status().is_success() {
+        let h = response.headers().get("etag");
+        let etag = if let Some(etag) = h {
+            Some(
+                etag.to_str()
+                    .map(|v| v.to_owned())
+                    .map_err(|err| AttemptError::fatal(err.to_string()))?,
+            )
+        } else {
+            None
+        };
+        if let (Some(expected), Some(etag)) = (expected_md5_hex, &etag) {
+            // A mismatch means the body was corrupted on the way
+            verify_etag(etag, expected).map_err(AttemptError::retryable)?;
+        }
+        Ok(etag)
+    } else {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        if chunked {
+            if let Some(message) = chunked_rejection(status, &body) {
+                return Err(AttemptError::fatal(message));
+            }
+        }
+        Err(AttemptError {
+            error: status_error(status, body),
+            retryable: status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS,
+        })
+    }
+}
+
+/// Compares a single-part ETag with the MD5 of what was sent. Multipart
+/// ETags (`"<hex>-<parts>"`) and ETags that aren't a plain MD5 are not
+/// checked, since they don't hash the whole body.
+fn verify_etag(etag: &str, expected_md5_hex: &str) -> Result<(), String> {
+    let value = etag.trim_start_matches("W/").trim_matches('"');
+    if value.len() != 32 || !value.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Ok(());
+    }
+    if !value.eq_ignore_ascii_case(expected_md5_hex) {
+        return Err(format!(
+            "MD5 mismatch: expected {} but the server stored {}",
+            expected_md5_hex, value
+        ));
+    }
+    Ok(())
+}
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct Token {
-    auth: String,
-    two_factor: String,
+/// Rejects a range that runs past the end of the file before anything is
+/// sent. Otherwise the body would come up short of its Content-Length and
+/// the server would hang or fail with a confusing length mismatch.
+async fn check_file_range(range: &FileRange<'_>) -> Result<(), CommandError> {
+    let file_len = tokio::fs::metadata(range.path).await?.len();
+    // A chunked upload sends whatever follows `start`
+    let end = if range.chunked {
+        Some(range.start)
+    } else {
+        range.start.checked_add(range.length)
+    };
+    if !matches!(end, Some(end) if range.start <= file_len && end <= file_len) {
+        return Err(format!(
+            "Range of {} bytes at {} is outside {}, which is {} bytes long",
+            range.length, range.start, range.path, file_len
+        )
+        .into());
+    }
+    Ok(())
 }
 
-#[tauri::command]
-fn save_token(username: String, token: Token) -> Result<(), String> {
-    let entry = Entry::new("third_vrchat_token", &username).map_err(|e| e.to_string())?;
-    let json = serde_json::to_string(&token).map_err(|e| e.to_string())?;
-    entry.set_password(&json).map_err(|e| e.to_string())
+/// Sends `range`, retrying failures worth retrying up to `max_retries`
+/// times with backoff.
+async fn put_with_retries(
+    client: &UploadClient,
+    range: &FileRange<'_>,
+    reporter: &Arc<UploadProgressReporter>,
+    max_retries: u32,
+) -> Result<Option<String>, CommandError> {
+    check_file_range(range).await?;
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match put_file_range(client, range, reporter.clone()).await {
+            Ok(etag) => return Ok(etag),
+            Err(err) if err.retryable && attempts <= max_retries => {
+                tokio::time::sleep(retry_delay(attempts - 1)).await;
+                reporter.restart();
+            }
+            Err(err) if attempts > 1 => return Err(err.error.after_attempts(attempts)),
+            Err(err) => return Err(err.error),
+        }
+    }
 }
 
-#[tauri::command]
-fn load_token(username: String) -> Result<Option<Token>, String> {
-    let entry = Entry::new("third_vrchat_token", &username).map_err(|e| e.to_string())?;
-    let res = entry.get_password();
-    match res {
-        Ok(json) => serde_json::from_str::<Token>(&json)
-            .map(|t| Some(t))
-            .map_err(|e| e.to_string()),
-        Err(keyring::Error::NoEntry) => Ok(None),
-        Err(err) => Err(err.to_string()),
+/// Turns caller supplied headers into a `HeaderMap`, refusing ones that are
+/// malformed or that would contradict how the body is sent.
+fn extra_headers(headers: HashMap<String, String>) -> Result<HeaderMap, String> {
+    let mut map = HeaderMap::with_capacity(headers.len());
+    for (name, value) in headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name: {:?}", name))?;
+        if header_name == CONTENT_LENGTH || header_name == TRANSFER_ENCODING {
+            return Err(format!(
+                "Header {} is set by the upload and can't be overridden",
+                header_name
+            ));
+        }
+        let header_value = HeaderValue::from_str(&value)
+            .map_err(|_| format!("Invalid value for header {}: {:?}", header_name, value))?;
+        map.append(header_name, header_value);
     }
+    Ok(map)
 }
 
+/// Uploads `length` bytes of `path` from `start`. With `chunked` set the
+/// rest of the file from `start` is sent with chunked transfer encoding and
+/// no `Content-Length`, for servers that accept bodies of unknown length.
+/// Progress is reported through `upload://progress` events. Connection
+/// errors, 5xx and 429 responses are retried up to `max_retries` times with
+/// backoff, reopening the file for each attempt. With `timeout_secs` an
+/// attempt that hasn't finished sending and receiving within that time
+/// fails with an error starting with "Timed out". `headers` are added to
+/// the request, e.g. ones a presigned URL's signature covers.
+///
+/// With `expected_md5_hex` the response ETag is compared to it, but only
+/// when the ETag is a plain hex MD5 as S3 returns for single-part PUTs;
+/// multipart and other ETags are accepted unchecked.
+///
+/// An upload given an `upload_id` can be aborted with `cancel_upload`, and
+/// `max_bytes_per_sec` caps its average rate.
 #[tauri::command]
-fn delete_token(username: String) -> Result<(), String> {
-    let entry = Entry::new("third_vrchat_token", &username).map_err(|e| e.to_string())?;
-    entry.delete_credential().map_err(|e| e.to_string())
+#[allow(clippy::too_many_arguments)]
+async fn upload_file(
+    app: AppHandle,
+    client: State<'_, Mutex<UploadClient>>,
+    registry: State<'_, JobRegistry>,
+    url: String,
+    path: String,
+    start: u64,
+    length: u64,
+    chunked: Option<bool>,
+    max_retries: Option<u32>,
+    timeout_secs: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+    expected_md5_hex: Option<String>,
+    upload_id: Option<String>,
+    max_bytes_per_sec: Option<u64>,
+) -> Result<Option<String>, CommandError> {
+    let headers = extra_headers(headers.unwrap_or_default())?;
+    let range = FileRange {
+        url: &url,
+        path: &path,
+        start,
+        length,
+        chunked: chunked.unwrap_or(false),
+        timeout: timeout_secs.map(std::time::Duration::from_secs),
+        headers: &headers,
+        expected_md5_hex: expected_md5_hex.as_deref(),
+        max_bytes_per_sec,
+    };
+    run_upload(
+        app,
+        &upload_client(&client),
+        &registry,
+        &range,
+        max_retries.unwrap_or(0),
+        upload_id,
+    )
+    .await
 }
 
-#[tauri::command]
-fn md5_digest_file(path: String) -> Result<String, String> {
-    let data = std::fs::read(&path).map_err(|e| e.to_string())?;
-    let hash = Md5::digest(&data);
-    let hashb64 = STANDARD.encode(&hash);
-    Ok(hashb64)
+/// Outcome of `upload_file_resumable`. `committed` counts the bytes of the
+/// range the server confirmed it stored; after a failure the rest can be
+/// sent again from `start + committed`.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResumableUpload {
+    etag: Option<String>,
+    error: Option<CommandError>,
+    committed: u64,
 }
 
+/// `upload_file`, but a failure is reported together with how much of the
+/// range the server stored. Bytes handed to the connection may not have
+/// reached it, so they aren't counted. With `query_committed` the server
+/// is asked with the resumable upload status query, an empty PUT with
+/// `Content-Range: bytes */<length>` answered by 308 and a `Range` header.
+/// That PUT would replace a plain object with an empty one, so without it,
+/// or when the server doesn't answer that way, `committed` is 0 and the
+/// range has to be sent again whole.
 #[tauri::command]
-async fn signature_generate_from_file(path: String, output: String) -> Result<(), String> {
-    let file = std::fs::File::open(&path).map_err(|e| e.to_string())?;
-    let mut signature = Signature::with_options(&file, 2048, 32, librsync::SignatureType::Blake2)
-        .map_err(|e| e.to_string())?;
-    let mut output_file = std::fs::File::create(&output).map_err(|e| e.to_string())?;
-    std::io::copy(&mut signature, &mut output_file).map_err(|e| e.to_string())?;
-    output_file.sync_all().map_err(|e| e.to_string())?;
-    Ok(())
+#[allow(clippy::too_many_arguments)]
+async fn upload_file_resumable(
+    app: AppHandle,
+    client: State<'_, Mutex<UploadClient>>,
+    registry: State<'_, JobRegistry>,
+    url: String,
+    path: String,
+    start: u64,
+    length: u64,
+    max_retries: Option<u32>,
+    timeout_secs: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+    upload_id: Option<String>,
+    max_bytes_per_sec: Option<u64>,
+    query_committed: Option<bool>,
+) -> Result<ResumableUpload, CommandError> {
+    let headers = extra_headers(headers.unwrap_or_default())?;
+    let range = FileRange {
+        url: &url,
+        path: &path,
+        start,
+        length,
+        chunked: false,
+        timeout: timeout_secs.map(std::time::Duration::from_secs),
+        headers: &headers,
+        expected_md5_hex: None,
+        max_bytes_per_sec,
+    };
+    let client = upload_client(&client);
+    let result = run_upload(
+        app,
+        &client,
+        &registry,
+        &range,
+        max_retries.unwrap_or(0),
+        upload_id,
+    )
+    .await;
+    Ok(match result {
+        Ok(etag) => ResumableUpload {
+            etag,
+            error: None,
+            committed: length,
+        },
+        Err(err) => ResumableUpload {
+            etag: None,
+            error: Some(err),
+            committed: if query_committed.unwrap_or(false) {
+                committed_length(&client, &range).await
+            } else {
+                0
+            },
+        },
+    })
 }
 
-#[tauri::command]
-async fn unpack_bundle(app_handle: tauri::AppHandle, path: String) -> Result<String, String> {
-    tokio::task::spawn_blocking(move || {
-        let file = File::open(&path).map_err(|e| e.to_string())?;
-        let reader = BufReader::new(file);
-        let mut archive = ZipArchive::new(reader).map_err(|e| e.to_string())?;
-        let app_dir = app_handle
-            .path()
-            .app_data_dir()
-            .map_err(|e| e.to_string())?;
+/// Asks the server how many bytes of `range` it has stored, through the
+/// resumable upload status query. Anything but a 308 with a `Range` header
+/// counts as nothing stored.
+async fn committed_length(client: &UploadClient, range: &FileRange<'_>) -> u64 {
+    match status_query(client, range).send().await {
+        Ok(response) if response.status() == StatusCode::PERMANENT_REDIRECT => response
+            .headers()
+            .get(RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(committed_range_end)
+            .map_or(0, |end| end.min(range.length)),
+        _ => 0,
+    }
+}
 
-        let mut tmp = PathBuf::from("bundles");
+/// The empty PUT `committed_length` sends.
+fn status_query(client: &UploadClient, range: &FileRange<'_>) -> reqwest::RequestBuilder {
+    let request = client
+        .client
+        .put(range.url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .headers(range.headers.clone())
+        .header(CONTENT_RANGE, format!("bytes */{}", range.length))
+        .header(CONTENT_LENGTH, "0");
+    match range.timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
 
-        let random_bytes: [u8; 16] = rand::thread_rng().gen();
-        let target_dir = random_bytes
-            .iter()
-            .map(|byte| format!("{:02x}", byte))
-            .collect::<String>();
-        tmp.push(target_dir);
+/// End of the stored prefix in a status query's `Range: bytes=0-<last>`.
+fn committed_range_end(range: &str) -> Option<u64> {
+    let (first, last) = range.trim().strip_prefix("bytes=")?.split_once('-')?;
+    if first != "0" {
+        return None;
+    }
+    last.parse::<u64>().ok()?.checked_add(1)
+}
 
-        let dst = app_dir.join(&tmp);
+/// Sends `range` with progress events and cancellation by `upload_id`.
+async fn run_upload(
+    app: AppHandle,
+    client: &UploadClient,
+    registry: &JobRegistry,
+    range: &FileRange<'_>,
+    max_retries: u32,
+    upload_id: Option<String>,
+) -> Result<Option<String>, CommandError> {
+    let cancel = CancellationToken::new();
+    let _registration = match upload_id
+        .clone()
+        .map(|id| registry.register(id, cancel.clone()))
+        .transpose()
+    {
+        Ok(registration) => registration,
+        Err(err) => return Err(err.into()),
+    };
+    let reporter = Arc::new(UploadProgressReporter::new(
+        app,
+        upload_id,
+        range.url.to_owned(),
+        range.path.to_owned(),
+        (!range.chunked).then_some(range.length),
+    ));
 
-        std::fs::create_dir_all(&dst).map_err(|err| err.to_string())?;
-        match archive.extract(&dst) {
-            Ok(_) => Ok(dst.to_string_lossy().into_owned()),
-            Err(err) => {
-                std::fs::remove_dir_all(&dst).map_err(|err| err.to_string())?;
-                Err(err.to_string())
+    // Dropping the request on cancellation aborts the transfer
+    let result = tokio::select! {
+        result = put_with_retries(client, range, &reporter, max_retries) => result,
+        _ = cancel.cancelled() => Err(CommandError::Cancelled("Upload")),
+    };
+    reporter.finish(&result);
+    result
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UploadPart {
+    url: String,
+    start: u64,
+    length: u64,
+}
+
+/// Outcome of one part, so failed parts can be retried on their own.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct PartResult {
+    index: usize,
+    etag: Option<String>,
+    error: Option<String>,
+}
+
+/// Uploads the parts of `path` with at most `max_concurrency` in flight,
+/// each opening the file on its own. Results are in part order.
+/// `timeout_secs` and `headers` apply to every part as they do in
+/// `upload_file`.
+///
+/// With an `upload_id` the whole session can be cancelled through
+/// `cancel_upload`, which then also sends the abort request to
+/// `abort_url` so the server drops the parts uploaded so far. If that
+/// request fails its error is returned instead of "Upload cancelled".
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn upload_parts(
+    app: AppHandle,
+    client: State<'_, Mutex<UploadClient>>,
+    registry: State<'_, JobRegistry>,
+    path: String,
+    parts: Vec<UploadPart>,
+    max_concurrency: Option<usize>,
+    max_retries: Option<u32>,
+    timeout_secs: Option<u64>,
+    headers: Option<HashMap<String, String>>,
+    upload_id: Option<String>,
+    abort_url: Option<String>,
+) -> Result<Vec<PartResult>, CommandError> {
+    let max_concurrency = max_concurrency.unwrap_or(4).max(1);
+    let max_retries = max_retries.unwrap_or(0);
+    let timeout = timeout_secs.map(std::time::Duration::from_secs);
+    let headers = extra_headers(headers.unwrap_or_default())?;
+    let client = &upload_client(&client);
+    let cancel = CancellationToken::new();
+    let _registration = upload_id
+        .map(|id| registry.register(id, cancel.clone()))
+        .transpose()?;
+
+    let upload = futures_util::stream::iter(parts.into_iter().enumerate())
+        .map(|(index, part)| {
+            let app = app.clone();
+            let path = &path;
+            let headers = &headers;
+            async move {
+                let reporter = Arc::new(UploadProgressReporter::new(
+                    app,
+                    None,
+                    part.url.clone(),
+                    path.clone(),
+                    Some(part.length),
+                ));
+                let range = FileRange {
+                    url: &part.url,
+                    path,
+                    start: part.start,
+                    length: part.length,
+                    chunked: false,
+                    timeout,
+                    headers,
+                    expected_md5_hex: None,
+                    max_bytes_per_sec: None,
+                };
+                let result = put_with_retries(client, &range, &reporter, max_retries).await;
+                reporter.finish(&result);
+                match result {
+                    Ok(etag) => PartResult {
+                        index,
+                        etag,
+                        error: None,
+                    },
+                    Err(err) => PartResult {
+                        index,
+                        etag: None,
+                        error: Some(err.to_string()),
+                    },
+                }
+            }
+        })
+        .buffered(max_concurrency)
+        .collect::<Vec<_>>();
+
+    // Dropping the part uploads on cancellation aborts their transfers
+    tokio::select! {
+        results = upload => Ok(results),
+        _ = cancel.cancelled() => {
+            if let Some(abort_url) = &abort_url {
+                send_abort(client, abort_url).await?;
             }
+            Err(CommandError::Cancelled("Upload"))
         }
-    })
-    .await
-    .map_err(|e| e.to_string())?
+    }
+}
+
+/// Asks the server to discard a multipart upload, e.g. S3's
+/// `DELETE ?uploadId=...`, so its parts stop taking up storage.
+async fn send_abort(client: &UploadClient, abort_url: &str) -> Result<(), CommandError> {
+    let response = client
+        .client
+        .delete(abort_url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await
+        .map_err(|err| client.request_error(abort_url, err))?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(status_error(
+            status,
+            response.text().await.unwrap_or_default(),
+        ));
+    }
+    Ok(())
 }
 
+/// Aborts a multipart upload on the server without a running session, for
+/// cleaning up after a failed or abandoned upload.
 #[tauri::command]
-async fn transcode_bundle(path: String, output: String) -> Result<(), String> {
-    let input_file = File::open(&path).map_err(|err| err.to_string())?;
-    let reader = BufReader::new(input_file);
-    let decoder = AssetBundleDecoder::new(reader);
-    let mut bundle = decoder.decode().map_err(|err| err.to_string())?;
+async fn abort_multipart(
+    client: State<'_, Mutex<UploadClient>>,
+    abort_url: String,
+) -> Result<(), CommandError> {
+    send_abort(&upload_client(&client), &abort_url).await
+}
 
-    bundle.set_blocks_lzma();
+/// Aborts the upload started with `upload_id`, which then fails with
+/// "Upload cancelled". Returns whether such an upload was running.
+#[tauri::command]
+fn cancel_upload(registry: State<'_, JobRegistry>, upload_id: String) -> bool {
+    registry.cancel(&upload_id)
+}
 
-    let output_file = File::create(&output).map_err(|err| err.to_string())?;
-    let writer = std::io::BufWriter::new(output_file);
-    let encoder = AssetBundleEncoder::new(writer);
-    encoder.encode(&bundle).map_err(|err| err.to_string())?;
-    Ok(())
+/// Stops the transcode started with `job_id`, which then fails with
+/// "Transcode cancelled". Returns whether such a transcode was running.
+#[tauri::command]
+fn cancel_transcode(registry: State<'_, JobRegistry>, job_id: String) -> bool {
+    registry.cancel(&job_id)
 }
 
-const USER_AGENT: &str = "Third Uploader/1.0.0 contact@third3d.com";
+/// Forwards everything written to it as bounded chunks over a channel,
+/// hashing the bytes on the way through. Blocks when the channel is full so
+/// the encoder can't run ahead of the upload.
+struct ChannelWriter {
+    sender: tokio::sync::mpsc::Sender<std::io::Result<Vec<u8>>>,
+    hasher: Md5,
+    closed: bool,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let chunk = &buf[..buf.len().min(64 * 1024)];
+        if self.sender.blocking_send(Ok(chunk.to_vec())).is_err() {
+            self.closed = true;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "upload stream closed",
+            ));
+        }
+        self.hasher.update(chunk);
+        Ok(chunk.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StreamUpload {
+    md5: String,
+    etag: Option<String>,
+}
 
 #[tauri::command]
-async fn upload_file(
-    url: String,
+async fn transcode_stream_upload(
+    app: AppHandle,
+    client: State<'_, Mutex<UploadClient>>,
     path: String,
-    start: u64,
-    length: u64,
-) -> Result<Option<String>, String> {
-    let mut file = tokio::fs::File::open(&path)
-        .await
-        .map_err(|err| err.to_string())?;
-    file.seek(SeekFrom::Start(start))
-        .await
-        .map_err(|err| err.to_string())?;
-    let stream = ReaderStream::new(file.take(length));
+    url: String,
+    target: Option<String>,
+) -> Result<StreamUpload, CommandError> {
+    let client = upload_client(&client);
+    let defaults = settings::load_transcode_defaults(&app);
+    let compression =
+        parse_target_compression(target.as_deref().unwrap_or(&defaults.target_compression))?;
 
-    let client = reqwest::Client::new();
-    let request = client
-        .put(url)
+    let (sender, receiver) = tokio::sync::mpsc::channel(8);
+
+    // Err(None) means the upload side hung up first, so its error wins
+    let encoder_task =
+        tokio::task::spawn_blocking(move || -> Result<String, Option<CommandError>> {
+            let mut writer = ChannelWriter {
+                sender,
+                hasher: Md5::new(),
+                closed: false,
+            };
+            let result = File::open(&path)
+                .map_err(CommandError::from)
+                .and_then(|file| {
+                    let decoder = AssetBundleDecoder::new(BufReader::new(file));
+                    // Block by block through a spool file, as in
+                    // `transcode_bundle`, so memory stays bounded by the
+                    // block size
+                    AssetBundleEncoder::new(&mut writer)
+                        .lzma_preset(defaults.lzma_preset)
+                        .block_size(Some(defaults.block_size))
+                        .transcode(decoder, compression, None)
+                        .map_err(CommandError::from)
+                });
+            match result {
+                Ok(()) => Ok(STANDARD.encode(writer.hasher.finalize())),
+                Err(_) if writer.closed => Err(None),
+                Err(err) => {
+                    // Fail the body stream so the request is aborted
+                    let _ = writer
+                        .sender
+                        .blocking_send(Err(std::io::Error::other(err.to_string())));
+                    Err(Some(err))
+                }
+            }
+        });
+
+    let stream = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+
+    let response = client
+        .client
+        .put(&url)
         .header(reqwest::header::USER_AGENT, USER_AGENT)
-        .header(CONTENT_LENGTH, length.to_string())
-        .body(Body::wrap_stream(stream));
+        .body(Body::wrap_stream(stream))
+        .send()
+        .await;
+
+    let encoded = encoder_task.await.map_err(|err| err.to_string())?;
+    let (response, md5) = match (response, encoded) {
+        (_, Err(Some(err))) => return Err(err),
+        (Err(err), _) => return Err(client.request_error(&url, err)),
+        (Ok(_), Err(None)) => return Err("Upload stream closed unexpectedly".into()),
+        (Ok(response), Ok(md5)) => (response, md5),
+    };
 
-    let response = request.send().await.map_err(|err| err.to_string())?;
     if response.status().is_success() {
-        let h = response.headers().get("etag");
-        let etag = if let Some(etag) = h {
-            Some(
+        let etag = match response.headers().get("etag") {
+            Some(etag) => Some(
                 etag.to_str()
                     .map(|v| v.to_owned())
                     .map_err(|err| err.to_string())?,
-            )
-        } else {
-            None
+            ),
+            None => None,
         };
-        Ok(etag)
+        Ok(StreamUpload { md5, etag })
     } else {
-        Err(format!(
-            "{}: {}",
-            response.status().as_str(),
-            response.text().await.unwrap_or_default()
-        ))
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        Err(match chunked_rejection(status, &body) {
+            Some(message) => message.into(),
+            None => status_error(status, body),
+        })
+    }
+}
+
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct PresignInfo {
+    /// "sigv4" for AWS Signature Version 4 query signing, otherwise "unknown".
+    kind: &'static str,
+    signed_at: Option<u64>,
+    expires_at: Option<u64>,
+    expires_in: Option<i64>,
+    valid: Option<bool>,
+    signed_headers: Vec<String>,
+}
+
+/// Reads the expiry of a presigned URL from its query string without any
+/// network access. The HTTP method is covered by the signature but not
+/// spelled out in the URL, so only the signed headers can be reported.
+#[tauri::command]
+fn inspect_presigned_url(url: String) -> Result<PresignInfo, CommandError> {
+    catch_panics(|| presign_info(&url))
+}
+
+fn presign_info(url: &str) -> Result<PresignInfo, CommandError> {
+    let url = Url::parse(url).map_err(|e| e.to_string())?;
+
+    let mut algorithm = None;
+    let mut date = None;
+    let mut expires = None;
+    let mut signed_headers = Vec::new();
+    for (key, value) in url.query_pairs() {
+        match key.to_ascii_lowercase().as_str() {
+            "x-amz-algorithm" => algorithm = Some(value.into_owned()),
+            "x-amz-date" => date = parse_amz_date(&value),
+            "x-amz-expires" => expires = value.parse::<u64>().ok(),
+            "x-amz-signedheaders" => {
+                signed_headers = value.split(';').map(str::to_owned).collect();
+            }
+            _ => {}
+        }
+    }
+
+    let (Some("AWS4-HMAC-SHA256"), Some(signed_at), Some(expires)) =
+        (algorithm.as_deref(), date, expires)
+    else {
+        return Ok(PresignInfo {
+            kind: "unknown",
+            ..Default::default()
+        });
+    };
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_secs();
+    // An expiry past what a timestamp can hold is unknown, and no server
+    // would accept the URL
+    let expires_at = signed_at.checked_add(expires);
+    Ok(PresignInfo {
+        kind: "sigv4",
+        signed_at: Some(signed_at),
+        expires_at,
+        expires_in: expires_at
+            .and_then(|expires_at| i64::try_from(expires_at).ok())
+            .map(|expires_at| expires_at - now as i64),
+        valid: Some(
+            expires_at.is_some_and(|expires_at| signed_at <= now + 300 && now < expires_at),
+        ),
+        signed_headers,
+    })
+}
+
+/// Parses an `X-Amz-Date` timestamp (`20240131T235959Z`) into unix seconds.
+fn parse_amz_date(value: &str) -> Option<u64> {
+    if value.len() != 16 || value.get(8..9) != Some("T") || value.get(15..) != Some("Z") {
+        return None;
+    }
+    let field = |start: usize, end: usize| value.get(start..end)?.parse::<i64>().ok();
+    let (year, month, day) = (field(0, 4)?, field(4, 6)?, field(6, 8)?);
+    let (hour, minute, second) = (field(9, 11)?, field(11, 13)?, field(13, 15)?);
+    if year < 1970
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    // Days since the epoch in the proleptic Gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// Largest payload a speed probe will send to a single endpoint.
+const PROBE_MAX_BYTES: u64 = 4 * 1024 * 1024;
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct EndpointSpeed {
+    url: String,
+    latency_ms: Option<u64>,
+    bytes_per_sec: Option<u64>,
+    error: Option<String>,
+}
+
+/// Probes through the upload client, so a proxy set with `set_proxy` is
+/// measured on the same route the uploads take.
+#[tauri::command]
+async fn probe_endpoint_speed(
+    client: State<'_, Mutex<UploadClient>>,
+    urls: Vec<String>,
+    sample_bytes: u64,
+) -> Result<Vec<EndpointSpeed>, CommandError> {
+    let mut payload = vec![0u8; sample_bytes.min(PROBE_MAX_BYTES) as usize];
+    rand::thread_rng().fill(&mut payload[..]);
+
+    let client = upload_client(&client).client;
+    let mut results = Vec::with_capacity(urls.len());
+    // One at a time so the probes don't compete for bandwidth
+    for url in urls {
+        results.push(probe_endpoint(&client, url, &payload).await);
     }
+    Ok(results)
+}
+
+async fn probe_endpoint(client: &reqwest::Client, url: String, payload: &[u8]) -> EndpointSpeed {
+    let mut speed = EndpointSpeed {
+        url,
+        latency_ms: None,
+        bytes_per_sec: None,
+        error: None,
+    };
+
+    // Any response, even an error status, is a valid round trip
+    let started = Instant::now();
+    let response = client
+        .head(&speed.url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .send()
+        .await;
+    match response {
+        Ok(_) => speed.latency_ms = Some(started.elapsed().as_millis() as u64),
+        Err(err) => {
+            speed.error = Some(err.to_string());
+            return speed;
+        }
+    }
+
+    if payload.is_empty() {
+        return speed;
+    }
+
+    let started = Instant::now();
+    let response = client
+        .put(&speed.url)
+        .header(reqwest::header::USER_AGENT, USER_AGENT)
+        .body(payload.to_vec())
+        .send()
+        .await;
+    match response {
+        Ok(response) if response.status().is_success() => {
+            let elapsed = started.elapsed().as_secs_f64().max(0.001);
+            speed.bytes_per_sec = Some((payload.len() as f64 / elapsed) as u64);
+            // Best effort, not every endpoint allows removing the probe
+            let _ = client
+                .delete(&speed.url)
+                .header(reqwest::header::USER_AGENT, USER_AGENT)
+                .send()
+                .await;
+        }
+        Ok(response) => {
+            speed.error = Some(format!(
+                "{}: {}",
+                response.status().as_str(),
+                response.text().await.unwrap_or_default()
+            ))
+        }
+        Err(err) => speed.error = Some(err.to_string()),
+    }
+    speed
 }
 
 #[tauri::command]
-async fn file_arg(app: tauri::AppHandle) -> Result<Option<String>, String> {
+async fn file_arg(app: tauri::AppHandle) -> Result<Option<String>, CommandError> {
     if let Some(arg) = std::env::args().nth(1) {
         app.asset_protocol_scope()
             .allow_file(&arg)
@@ -199,18 +3109,549 @@ fn main() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_fs::init())
         .plugin(upload::init())
+        .manage(Mutex::new(
+            UploadClient::new(None).expect("error while building the upload HTTP client"),
+        ))
+        .manage(JobRegistry::default())
+        .manage(file_watcher::FileWatcherState::default())
         .invoke_handler(tauri::generate_handler![
             save_token,
             load_token,
             delete_token,
+            token_exists_and_valid,
+            save_token_cas,
+            migrate_token,
+            list_accounts,
             md5_digest_file,
+            digest_file,
             signature_generate_from_file,
+            signature_generate_bytes,
+            estimate_delta_savings,
+            delta_generate_from_file,
+            patch_apply,
             unpack_bundle,
+            remove_bundle,
+            cleanup_bundles,
             upload_file,
+            upload_file_resumable,
+            cancel_upload,
+            cancel_transcode,
+            set_proxy,
+            upload_parts,
+            abort_multipart,
             transcode_bundle,
+            estimate_transcode,
+            get_transcode_defaults,
+            set_transcode_defaults,
             file_arg,
+            capabilities,
+            keyring_available,
+            check_bundle_size_field,
+            fix_bundle_size_field,
+            check_unity_version,
+            validate_bundle,
+            transcode_stream_upload,
+            bundle_asset_names,
+            detect_platform,
+            bundle_object_types,
+            bundle_textures,
+            bundle_mesh_stats,
+            bundle_shaders,
+            list_entries,
+            extract_entry,
+            replace_entry,
+            probe_endpoint_speed,
+            decompress_block,
+            verify_roundtrip,
+            inspect_presigned_url,
+            fuzz_truncate_decode,
+            bundle_padding_report,
+            export_directory_info,
+            inspect_bundle_from_url,
+            bundle_info,
+            bundle_info_bytes,
+            transcode_bundle_bytes,
+            measure_decompression_speed,
+            check_custom_size_limit,
+            file_watcher::start_watch,
+            file_watcher::stop_watch,
             upload::upload
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presigned_url_with_overflowing_expiry_is_invalid() {
+        let url = format!(
+            "https://bucket.s3.amazonaws.com/key?X-Amz-Algorithm=AWS4-HMAC-SHA256\
+             &X-Amz-Date=20240131T235959Z&X-Amz-Expires={}&X-Amz-SignedHeaders=host",
+            u64::MAX
+        );
+        let info = inspect_presigned_url(url).unwrap();
+        assert_eq!(info.kind, "sigv4");
+        assert_eq!(info.signed_at, Some(1706745599));
+        assert_eq!(info.expires_at, None);
+        assert_eq!(info.expires_in, None);
+        assert_eq!(info.valid, Some(false));
+    }
+
+    fn zip_with(names: &[&str]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for name in names {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"bundle").unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn extract_archive_rejects_path_traversal() {
+        let root = tempfile::tempdir().unwrap();
+        let dst = root.path().join("unpacked");
+        for name in ["../evil", "nested/../../evil", "/evil"] {
+            std::fs::create_dir_all(&dst).unwrap();
+            let result = extract_archive(&mut zip_with(&["ok.vrca", name]), &dst);
+            assert!(result.is_err(), "{}", name);
+            assert!(!root.path().join("evil").exists(), "{}", name);
+        }
+
+        extract_archive(&mut zip_with(&["nested/avatar.vrca"]), &dst).unwrap();
+        assert_eq!(
+            std::fs::read(dst.join("nested/avatar.vrca")).unwrap(),
+            b"bundle"
+        );
+    }
+
+    #[test]
+    fn transcode_base64_applies_encoder_options() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lzma, 1);
+        let input = STANDARD.encode(bundle::tests::encode_with(&bundle, |encoder| encoder));
+        let output = transcode_base64(&input, Some(Compression::Lz4Hc), |encoder| {
+            encoder.block_size(Some(64 * 1024)).lz4hc_level(12)
+        })
+        .unwrap();
+        let output = STANDARD.decode(output).unwrap();
+        let transcoded = AssetBundleDecoder::new(Cursor::new(output.clone()))
+            .decode()
+            .unwrap();
+        assert_eq!(transcoded.info().blocks.len(), 5);
+        assert!(bundle.first_content_difference(&transcoded).is_none());
+
+        // Level 12 compresses at least as well as level 1
+        let fast = transcode_base64(&input, Some(Compression::Lz4Hc), |encoder| {
+            encoder.block_size(Some(64 * 1024)).lz4hc_level(1)
+        })
+        .unwrap();
+        assert!(output.len() <= STANDARD.decode(fast).unwrap().len());
+
+        assert!(transcode_base64("not base64!", None, |encoder| encoder).is_err());
+    }
+
+    #[test]
+    fn patch_apply_rebuilds_file_from_delta() {
+        use tauri::async_runtime::block_on;
+
+        let basis: Vec<u8> = (0..1024 * 1024u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect();
+        // Bytes changed in place, inserted and removed
+        let mut modified = basis.clone();
+        modified[1000..1100].fill(0);
+        modified.splice(500_000..500_000, b"inserted".iter().copied());
+        modified.drain(900_000..900_500);
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+        std::fs::write(path("basis"), &basis).unwrap();
+        std::fs::write(path("modified"), &modified).unwrap();
+
+        block_on(signature_generate_from_file(
+            path("basis"),
+            path("signature"),
+            None,
+            None,
+            None,
+        ))
+        .unwrap();
+        let delta_size = block_on(delta_generate_from_file(
+            path("signature"),
+            path("modified"),
+            path("delta"),
+        ))
+        .unwrap();
+        assert!(delta_size < modified.len() as u64 / 10);
+        let size = block_on(patch_apply(path("basis"), path("delta"), path("patched"))).unwrap();
+        assert_eq!(size, modified.len() as u64);
+        assert_eq!(std::fs::read(path("patched")).unwrap(), modified);
+    }
+
+    #[test]
+    fn status_query_builds() {
+        let client = UploadClient::new(None).unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-goog-meta-id", HeaderValue::from_static("42"));
+        let range = FileRange {
+            url: "https://example.com/upload",
+            path: "",
+            start: 0,
+            length: 1000,
+            chunked: false,
+            timeout: Some(std::time::Duration::from_secs(5)),
+            headers: &headers,
+            expected_md5_hex: None,
+            max_bytes_per_sec: None,
+        };
+        let request = status_query(&client, &range).build().unwrap();
+        assert_eq!(request.method(), reqwest::Method::PUT);
+        let headers = request.headers();
+        assert_eq!(headers[reqwest::header::USER_AGENT], USER_AGENT);
+        assert_eq!(headers[CONTENT_RANGE], "bytes */1000");
+        assert_eq!(headers[CONTENT_LENGTH], "0");
+        assert_eq!(headers["x-goog-meta-id"], "42");
+        assert_eq!(request.timeout(), range.timeout.as_ref());
+    }
+
+    #[test]
+    fn file_range_past_end_is_rejected() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&[0; 100]).unwrap();
+        let path = file.path().to_str().unwrap();
+        let headers = HeaderMap::new();
+        let check = |start: u64, length: u64, chunked: bool| {
+            tauri::async_runtime::block_on(check_file_range(&FileRange {
+                url: "https://example.com/upload",
+                path,
+                start,
+                length,
+                chunked,
+                timeout: None,
+                headers: &headers,
+                expected_md5_hex: None,
+                max_bytes_per_sec: None,
+            }))
+        };
+
+        assert!(check(0, 100, false).is_ok());
+        assert!(check(100, 0, false).is_ok());
+        assert!(check(100, 0, true).is_ok());
+        for (start, length, chunked) in [
+            (50, 60, false),
+            (101, 0, false),
+            (u64::MAX, 2, false),
+            (101, 0, true),
+        ] {
+            let err = check(start, length, chunked).unwrap_err();
+            assert!(err.to_string().ends_with("100 bytes long"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn committed_range_end_reads_stored_prefix() {
+        assert_eq!(committed_range_end("bytes=0-1023"), Some(1024));
+        assert_eq!(committed_range_end(" bytes=0-0 "), Some(1));
+        // Only a prefix from the start says how far the upload got
+        assert_eq!(committed_range_end("bytes=512-1023"), None);
+        assert_eq!(committed_range_end("bytes=0-"), None);
+        assert_eq!(committed_range_end("items=0-10"), None);
+        assert_eq!(committed_range_end(&format!("bytes=0-{}", u64::MAX)), None);
+    }
+
+    /// A UnityFS header declaring `size`, followed by `tail_len` bytes.
+    fn bundle_header_file(size: u64, tail_len: usize) -> tempfile::NamedTempFile {
+        let mut header = b"UnityFS\0".to_vec();
+        header.extend(8u32.to_be_bytes());
+        header.extend(b"5.x.x\0");
+        header.extend(b"2022.3.22f1\0");
+        header.extend(size.to_be_bytes());
+        header.extend([0; 12]);
+        header.resize(header.len() + tail_len, 0);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&header).unwrap();
+        file
+    }
+
+    #[test]
+    fn size_check_reports_difference() {
+        let file = bundle_header_file(40, 100);
+        let check = size_check(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(check.actual, 150);
+        assert_eq!(check.difference, 110);
+        assert!(!check.matches);
+
+        // A size field beyond i64 saturates instead of overflowing
+        let file = bundle_header_file(u64::MAX, 0);
+        let check = size_check(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(check.declared, u64::MAX);
+        assert_eq!(check.difference, i64::MIN);
+    }
+
+    #[test]
+    fn saturating_difference_stays_in_range() {
+        assert_eq!(saturating_difference(10, 25), -15);
+        assert_eq!(saturating_difference(u64::MAX, 0), i64::MAX);
+        assert_eq!(saturating_difference(0, u64::MAX), i64::MIN);
+        assert_eq!(saturating_difference(u64::MAX, u64::MAX - 1), 1);
+    }
+
+    #[test]
+    fn verify_roundtrip_accepts_reblocked_transcode() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lz4Hc, 3);
+        let encoded = bundle::tests::encode_with(&bundle, |encoder| encoder);
+        let reblocked = bundle::tests::transcode_with(&encoded, None, |encoder| {
+            encoder.block_size(Some(64 * 1024))
+        });
+        let mut original = tempfile::NamedTempFile::new().unwrap();
+        original.write_all(&encoded).unwrap();
+        let mut transcoded = tempfile::NamedTempFile::new().unwrap();
+        transcoded.write_all(&reblocked).unwrap();
+        let path = |file: &tempfile::NamedTempFile| file.path().to_str().unwrap().to_owned();
+
+        let check = tauri::async_runtime::block_on(verify_roundtrip(
+            path(&original),
+            path(&transcoded),
+            None,
+        ))
+        .unwrap();
+        assert!(check.matches);
+
+        let check = tauri::async_runtime::block_on(verify_roundtrip(
+            path(&original),
+            path(&transcoded),
+            Some(true),
+        ))
+        .unwrap();
+        assert_eq!(check.difference.unwrap().field, "blockCount");
+    }
+
+    #[test]
+    fn verify_roundtrip_accepts_converted_legacy_bundles() {
+        let serialized = bundle::tests::sample_data(5000);
+        let resource = bundle::tests::sample_data(20000);
+        let path = |file: &tempfile::NamedTempFile| file.path().to_str().unwrap().to_owned();
+        for web in [false, true] {
+            let legacy = bundle::tests::legacy_bundle(
+                web,
+                &[("CAB-old", &serialized), ("CAB-old.resS", &resource)],
+            );
+            let converted =
+                bundle::tests::transcode_with(&legacy, Some(Compression::Lz4Hc), |encoder| encoder);
+            let mut original = tempfile::NamedTempFile::new().unwrap();
+            original.write_all(&legacy).unwrap();
+            let mut transcoded = tempfile::NamedTempFile::new().unwrap();
+            transcoded.write_all(&converted).unwrap();
+
+            // What `transcode_bundle` checks with `verify`
+            let difference = roundtrip_difference(
+                &path(&original),
+                &path(&transcoded),
+                AssetBundle::first_content_difference,
+            )
+            .unwrap();
+            assert!(difference.is_none(), "web: {web}");
+
+            let check = tauri::async_runtime::block_on(verify_roundtrip(
+                path(&original),
+                path(&transcoded),
+                None,
+            ))
+            .unwrap();
+            assert!(check.matches, "web: {web}");
+        }
+    }
+
+    #[test]
+    fn presigned_url_rejects_malformed_input() {
+        assert!(inspect_presigned_url("not a url".to_owned()).is_err());
+        assert!(inspect_presigned_url(String::new()).is_err());
+        for query in [
+            "",
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=garbage&X-Amz-Expires=60",
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=20241399T000000Z&X-Amz-Expires=60",
+            "X-Amz-Algorithm=AWS4-HMAC-SHA256&X-Amz-Date=20240131T235959Z&X-Amz-Expires=-1",
+            "X-Amz-Algorithm=HMAC&X-Amz-Date=20240131T235959Z&X-Amz-Expires=60",
+        ] {
+            let info = inspect_presigned_url(format!("https://example.com/key?{}", query)).unwrap();
+            assert_eq!(info.kind, "unknown", "{}", query);
+        }
+    }
+
+    #[test]
+    fn amz_date_rejects_malformed_timestamps() {
+        assert_eq!(parse_amz_date("19700101T000000Z"), Some(0));
+        for value in [
+            "",
+            "20240131T235959",
+            "20240131 235959Z",
+            "2024013XT235959Z",
+            "19690101T000000Z",
+            "20240131T245959Z",
+            "2024013\u{e9}T235959Z",
+        ] {
+            assert_eq!(parse_amz_date(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn proxy_rejects_malformed_url() {
+        let proxy = ProxyConfig {
+            url: "not a url".to_owned(),
+            username: None,
+            password: None,
+            no_proxy: None,
+        };
+        assert!(UploadClient::new(Some(proxy)).is_err());
+    }
+
+    /// Files that aren't bundles, or stop or lie partway through the header.
+    fn malformed_bundles() -> Vec<(&'static str, Vec<u8>)> {
+        let mut truncated = b"UnityFS\0".to_vec();
+        truncated.extend(8u32.to_be_bytes());
+        truncated.extend(b"5.x");
+        let mut oversized = b"UnityFS\0".to_vec();
+        oversized.extend(8u32.to_be_bytes());
+        oversized.extend(b"5.x.x\0");
+        oversized.extend(b"2022.3.22f1\0");
+        oversized.extend(100u64.to_be_bytes());
+        oversized.extend([0xFF; 12]);
+        vec![
+            ("empty", Vec::new()),
+            ("garbage", b"this is not an asset bundle".to_vec()),
+            ("truncated header", truncated),
+            ("oversized block info", oversized),
+        ]
+    }
+
+    #[test]
+    fn object_types_skip_unparsable_files() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lz4Hc, 1);
+        let bytes = bundle::tests::encode_with(&bundle, |encoder| encoder);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let types = tauri::async_runtime::block_on(bundle_object_types(path)).unwrap();
+        assert!(types.types.is_empty());
+        assert_eq!(types.skipped, ["CAB-sample"]);
+    }
+
+    #[test]
+    fn textures_skip_unparsable_files() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lz4Hc, 1);
+        let bytes = bundle::tests::encode_with(&bundle, |encoder| encoder);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let textures = tauri::async_runtime::block_on(bundle_textures(path)).unwrap();
+        assert!(textures.textures.is_empty());
+        assert_eq!(textures.skipped, ["CAB-sample"]);
+    }
+
+    #[test]
+    fn mesh_stats_skip_unparsable_files() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lz4Hc, 1);
+        let bytes = bundle::tests::encode_with(&bundle, |encoder| encoder);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let stats = tauri::async_runtime::block_on(bundle_mesh_stats(path)).unwrap();
+        assert!(stats.meshes.is_empty());
+        assert_eq!(stats.skipped, ["CAB-sample"]);
+    }
+
+    #[test]
+    fn shaders_skip_unparsable_files() {
+        let bundle = bundle::tests::sample_bundle(Compression::Lz4Hc, 1);
+        let bytes = bundle::tests::encode_with(&bundle, |encoder| encoder);
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(&bytes).unwrap();
+
+        let path = file.path().to_str().unwrap().to_owned();
+        let stats = tauri::async_runtime::block_on(bundle_shaders(path)).unwrap();
+        assert!(stats.shaders.is_empty());
+        assert_eq!(stats.skipped, ["CAB-sample"]);
+    }
+
+    #[test]
+    fn path_commands_reject_malformed_bundles() {
+        use tauri::async_runtime::block_on;
+
+        fn check<T>(command: &str, input: &str, result: Result<T, CommandError>) {
+            match result {
+                Ok(_) => panic!("{} accepted {}", command, input),
+                Err(err) => assert!(
+                    !err.to_string().starts_with("Internal error"),
+                    "{} panicked on {}: {}",
+                    command,
+                    input,
+                    err
+                ),
+            }
+        }
+
+        for (input, bytes) in malformed_bundles() {
+            let mut file = tempfile::NamedTempFile::new().unwrap();
+            file.write_all(&bytes).unwrap();
+            let path = || file.path().to_str().unwrap().to_owned();
+
+            check("bundle_info", input, block_on(bundle_info(path())));
+            check("list_entries", input, block_on(list_entries(path())));
+            check(
+                "bundle_asset_names",
+                input,
+                block_on(bundle_asset_names(path())),
+            );
+            check(
+                "bundle_object_types",
+                input,
+                block_on(bundle_object_types(path())),
+            );
+            check("bundle_textures", input, block_on(bundle_textures(path())));
+            check(
+                "bundle_mesh_stats",
+                input,
+                block_on(bundle_mesh_stats(path())),
+            );
+            check("bundle_shaders", input, block_on(bundle_shaders(path())));
+            check("detect_platform", input, block_on(detect_platform(path())));
+            check(
+                "measure_decompression_speed",
+                input,
+                block_on(measure_decompression_speed(path())),
+            );
+            check(
+                "decompress_block",
+                input,
+                block_on(decompress_block(path(), 0)),
+            );
+            check(
+                "bundle_padding_report",
+                input,
+                block_on(bundle_padding_report(path())),
+            );
+            check(
+                "bundle_info_bytes",
+                input,
+                block_on(bundle_info_bytes(STANDARD.encode(&bytes))),
+            );
+        }
+        check(
+            "bundle_info_bytes",
+            "bad base64",
+            block_on(bundle_info_bytes("not base64!".to_owned())),
+        );
+        check(
+            "bundle_info",
+            "a missing file",
+            block_on(bundle_info(String::new())),
+        );
+    }
+}
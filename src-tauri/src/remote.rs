@@ -0,0 +1,163 @@
+//! Reads bundle metadata over HTTP using range requests, so a remote bundle
+//! can be inspected without downloading its data blocks.
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use reqwest::{
+    header::{CONTENT_RANGE, RANGE, USER_AGENT},
+    StatusCode,
+};
+
+use crate::{
+    bundle::{AssetBundleDecoder, BundleInfo},
+    error::CommandError,
+};
+
+/// The header and, for most bundles, the block info fit in the first range.
+const INITIAL_RANGE: u64 = 64 * 1024;
+const MAX_FETCHES: usize = 8;
+
+/// Byte ranges of a remote file fetched so far.
+#[derive(Default)]
+struct RangeCache {
+    total_len: Option<u64>,
+    chunks: Vec<(u64, Vec<u8>)>,
+}
+
+impl RangeCache {
+    async fn fetch(
+        &mut self,
+        client: &reqwest::Client,
+        url: &str,
+        start: u64,
+        len: u64,
+    ) -> Result<(), CommandError> {
+        let mut end = start.saturating_add(len) - 1;
+        if let Some(total_len) = self.total_len {
+            if start >= total_len {
+                return Err("Bundle is truncated".into());
+            }
+            end = end.min(total_len - 1);
+        }
+
+        let response = client
+            .get(url)
+            .header(USER_AGENT, crate::USER_AGENT)
+            .header(RANGE, format!("bytes={}-{}", start, end))
+            .send()
+            .await?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let total_len = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.rsplit_once('/'))
+                    .and_then(|(_, total)| total.parse::<u64>().ok());
+                if total_len.is_some() {
+                    self.total_len = total_len;
+                }
+                let bytes = response.bytes().await?;
+                self.chunks.push((start, bytes.to_vec()));
+            }
+            // The server ignored the range and sent the whole file
+            StatusCode::OK => {
+                let bytes = response.bytes().await?;
+                self.total_len = Some(bytes.len() as u64);
+                self.chunks = vec![(0, bytes.to_vec())];
+            }
+            status => {
+                return Err(CommandError::HttpStatus {
+                    code: status.as_u16(),
+                    body: response.text().await.unwrap_or_default(),
+                })
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads from the cached ranges, remembering the first offset that has not
+/// been fetched yet.
+struct SparseReader<'a> {
+    cache: &'a RangeCache,
+    position: u64,
+    missing: Option<u64>,
+}
+
+impl Read for SparseReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let chunk = self.cache.chunks.iter().find_map(|(start, data)| {
+            let offset = usize::try_from(self.position.checked_sub(*start)?).ok()?;
+            data.get(offset..).filter(|rest| !rest.is_empty())
+        });
+        match chunk {
+            Some(data) => {
+                let len = data.len().min(buf.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                self.position += len as u64;
+                Ok(len)
+            }
+            None => {
+                // Past the end of the file there is nothing more to fetch
+                if !matches!(self.cache.total_len, Some(total) if self.position >= total) {
+                    self.missing = Some(self.position);
+                }
+                Err(io::ErrorKind::UnexpectedEof.into())
+            }
+        }
+    }
+}
+
+impl Seek for SparseReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => {
+                let total_len = self.cache.total_len.ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::Unsupported, "Unknown remote file length")
+                })?;
+                total_len.checked_add_signed(offset)
+            }
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Fetches just enough of the bundle at `url` to decode its metadata,
+/// requesting further ranges when the header or block info lies outside
+/// what has been fetched so far.
+pub async fn fetch_bundle_info(url: &str) -> Result<BundleInfo, CommandError> {
+    let client = reqwest::Client::new();
+    let mut cache = RangeCache::default();
+    let mut start = 0;
+    let mut len = INITIAL_RANGE;
+
+    for _ in 0..MAX_FETCHES {
+        cache.fetch(&client, url, start, len).await?;
+
+        let mut reader = SparseReader {
+            cache: &cache,
+            position: 0,
+            missing: None,
+        };
+        let result = AssetBundleDecoder::new(&mut reader).decode_metadata();
+        match (result, reader.missing) {
+            (Ok(bundle), _) => return Ok(bundle.info()),
+            (Err(_), Some(offset)) => {
+                start = offset;
+                len *= 2;
+            }
+            (Err(err), None) => return Err(err.into()),
+        }
+    }
+    Err("Bundle metadata is too large to fetch".into())
+}
@@ -0,0 +1,582 @@
+use crate::bundle::BundleError;
+
+type Result<T> = std::result::Result<T, BundleError>;
+
+/// Oldest and newest SerializedFile format versions the parser understands.
+const MIN_VERSION: u32 = 14;
+const MAX_VERSION: u32 = 22;
+
+/// Unity's built-in string table. Type tree names with the high bit set in
+/// their offset point into this buffer instead of the file's own strings.
+const COMMON_STRINGS: &str = "AABB\0AnimationClip\0AnimationCurve\0AnimationState\0Array\0Base\0\
+BitField\0bitset\0bool\0char\0ColorRGBA\0Component\0data\0deque\0double\0dynamic_array\0\
+FastPropertyName\0first\0float\0Font\0GameObject\0Generic Mono\0GradientNEW\0GUID\0GUIStyle\0\
+int\0list\0long long\0map\0Matrix4x4f\0MdFour\0MonoBehaviour\0MonoScript\0m_ByteSize\0\
+m_Curve\0m_EditorClassIdentifier\0m_EditorHideFlags\0m_Enabled\0m_ExtensionPtr\0\
+m_GameObject\0m_Index\0m_IsArray\0m_IsStatic\0m_MetaFlag\0m_Name\0m_ObjectHideFlags\0\
+m_PrefabInternal\0m_PrefabParentObject\0m_Script\0m_StaticEditorFlags\0m_Type\0m_Version\0\
+Object\0pair\0PPtr<Component>\0PPtr<GameObject>\0PPtr<Material>\0PPtr<MonoBehaviour>\0\
+PPtr<MonoScript>\0PPtr<Object>\0PPtr<Prefab>\0PPtr<Sprite>\0PPtr<TextAsset>\0PPtr<Texture>\0\
+PPtr<Texture2D>\0PPtr<Transform>\0Prefab\0Quaternionf\0Rectf\0RectInt\0RectOffset\0second\0\
+set\0short\0size\0SInt16\0SInt32\0SInt64\0SInt8\0staticvector\0string\0TextAsset\0TextMesh\0\
+Texture\0Texture2D\0Transform\0TypelessData\0UInt16\0UInt32\0UInt64\0UInt8\0unsigned int\0\
+unsigned long long\0unsigned short\0vector\0Vector2f\0Vector3f\0Vector4f\0\
+m_ScriptingClassIdentifier\0Gradient\0Type*\0int2_storage\0int3_storage\0BoundsInt\0\
+m_CorrespondingSourceObject\0m_PrefabInstance\0m_PrefabAsset\0FileSize\0Hash128\0";
+
+/// Classes deriving from NamedObject, whose first serialized field is
+/// `m_Name`. Used when a file was built without type trees.
+const NAMED_CLASSES: &[i32] = &[
+    21, 27, 28, 43, 48, 49, 74, 83, 84, 89, 90, 91, 93, 115, 117, 128, 134, 142, 150, 152, 156,
+    187, 188, 213, 221, 319, 329,
+];
+
+/// Unity `BuildTarget` values for the platforms bundles are uploaded for.
+const BUILD_TARGET_STANDALONE_WINDOWS: i32 = 5;
+const BUILD_TARGET_IOS: i32 = 9;
+const BUILD_TARGET_ANDROID: i32 = 13;
+const BUILD_TARGET_STANDALONE_WINDOWS64: i32 = 19;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeTreeNode {
+    pub level: u8,
+    pub type_name: String,
+    pub name: String,
+    pub byte_size: i32,
+    pub meta_flag: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SerializedType {
+    pub class_id: i32,
+    pub nodes: Vec<TypeTreeNode>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub path_id: i64,
+    pub byte_start: u64,
+    pub byte_size: u32,
+    pub type_index: Option<usize>,
+    pub class_id: i32,
+}
+
+/// An object's fields as read through its type tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// An array of plain numbers, such as pixel or vertex data, kept as its
+    /// element count only.
+    Data(usize),
+    Array(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// The field called `name`, for a struct.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Struct(fields) => fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            Value::Bool(value) => Some(*value as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    /// Element count of either kind of array.
+    pub fn element_count(&self) -> Option<usize> {
+        match self {
+            Value::Data(len) => Some(*len),
+            Value::Array(values) => Some(values.len()),
+            _ => None,
+        }
+    }
+}
+
+/// Set on type tree nodes that are followed by padding to 4 bytes.
+const ALIGN_BYTES_FLAG: i32 = 0x4000;
+
+/// A parsed SerializedFile: header, type table and object table. Object
+/// data is left in place and read on demand.
+pub struct SerializedFile<'a> {
+    data: &'a [u8],
+    pub version: u32,
+    pub unity_version: String,
+    pub target_platform: i32,
+    big_endian: bool,
+    pub types: Vec<SerializedType>,
+    pub objects: Vec<ObjectInfo>,
+}
+
+impl<'a> SerializedFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self> {
+        let mut reader = Reader::new(data, true);
+
+        let mut metadata_size = reader.read_u32()? as u64;
+        let mut file_size = reader.read_u32()? as u64;
+        let version = reader.read_u32()?;
+        let mut data_offset = reader.read_u32()? as u64;
+
+        if !(MIN_VERSION..=MAX_VERSION).contains(&version) {
+            return Err(BundleError::UnsupportedBundle(format!(
+                "SerializedFile version {}",
+                version
+            )));
+        }
+
+        let big_endian = reader.read_u8()? != 0;
+        reader.skip(3)?;
+
+        if version >= 22 {
+            metadata_size = reader.read_u32()? as u64;
+            file_size = reader.read_u64()?;
+            data_offset = reader.read_u64()?;
+            reader.skip(8)?;
+        }
+
+        if file_size > data.len() as u64 || data_offset > file_size || metadata_size > file_size {
+            return Err(BundleError::InvalidData(
+                "SerializedFile header sizes exceed entry".to_owned(),
+            ));
+        }
+
+        reader.big_endian = big_endian;
+
+        let unity_version = reader.read_cstring()?;
+        let target_platform = reader.read_i32()?;
+        let enable_type_tree = reader.read_u8()? != 0;
+
+        let type_count = reader.read_count(8)?;
+        let mut types = Vec::with_capacity(type_count);
+        for _ in 0..type_count {
+            types.push(reader.read_type(version, enable_type_tree)?);
+        }
+
+        let object_count = reader.read_count(20)?;
+        let mut objects = Vec::with_capacity(object_count);
+        for _ in 0..object_count {
+            reader.align(4)?;
+            let path_id = reader.read_i64()?;
+            let byte_start = if version >= 22 {
+                reader.read_u64()?
+            } else {
+                reader.read_u32()? as u64
+            };
+            let byte_size = reader.read_u32()?;
+            let type_id = reader.read_i32()?;
+
+            let (type_index, class_id) = if version < 16 {
+                // Older files key their types by class id
+                let class_id = reader.read_u16()? as i32;
+                let index = types.iter().position(|t| t.class_id == type_id);
+                (index, class_id)
+            } else {
+                let index = usize::try_from(type_id)
+                    .ok()
+                    .filter(|index| *index < types.len())
+                    .ok_or_else(|| {
+                        BundleError::InvalidData(format!(
+                            "Object type index {} out of range",
+                            type_id
+                        ))
+                    })?;
+                (Some(index), types[index].class_id)
+            };
+            if version < 17 {
+                reader.skip(2)?; // script type index
+            }
+            if version == 15 || version == 16 {
+                reader.skip(1)?; // stripped
+            }
+
+            objects.push(ObjectInfo {
+                path_id,
+                byte_start: data_offset.saturating_add(byte_start),
+                byte_size,
+                type_index,
+                class_id,
+            });
+        }
+
+        Ok(Self {
+            data: &data[..file_size as usize],
+            version,
+            unity_version,
+            target_platform,
+            big_endian,
+            types,
+            objects,
+        })
+    }
+
+    /// The platform the file was built for, as `"pc"`, `"android"` or
+    /// `"ios"`, or `None` for any other build target.
+    pub fn platform_name(&self) -> Option<&'static str> {
+        match self.target_platform {
+            BUILD_TARGET_STANDALONE_WINDOWS | BUILD_TARGET_STANDALONE_WINDOWS64 => Some("pc"),
+            BUILD_TARGET_ANDROID => Some("android"),
+            BUILD_TARGET_IOS => Some("ios"),
+            _ => None,
+        }
+    }
+
+    /// The raw serialized bytes of an object, if they lie inside the file.
+    pub fn object_data(&self, object: &ObjectInfo) -> Option<&'a [u8]> {
+        let start = usize::try_from(object.byte_start).ok()?;
+        let end = start.checked_add(object.byte_size as usize)?;
+        self.data.get(start..end)
+    }
+
+    /// The type tree describing an object, when the file was built with one.
+    pub fn type_tree(&self, object: &ObjectInfo) -> Option<&[TypeTreeNode]> {
+        let nodes = &self.types.get(object.type_index?)?.nodes;
+        (!nodes.is_empty()).then_some(nodes.as_slice())
+    }
+
+    /// The class name from the object's type tree root, e.g. `Texture2D`.
+    pub fn type_name(&self, object: &ObjectInfo) -> Option<&str> {
+        self.type_tree(object)?
+            .first()
+            .map(|root| root.type_name.as_str())
+    }
+
+    /// Reads all of an object's fields through its type tree. Fails for
+    /// files built without type trees.
+    pub fn read_object(&self, object: &ObjectInfo) -> Result<Value> {
+        let nodes = self.type_tree(object).ok_or_else(|| {
+            BundleError::UnsupportedBundle(format!("Object {} has no type tree", object.path_id))
+        })?;
+        let data = self.object_data(object).ok_or_else(|| {
+            BundleError::InvalidData(format!(
+                "Object {} lies outside the SerializedFile",
+                object.path_id
+            ))
+        })?;
+        let subtree_ends = subtree_ends(nodes);
+        Reader::new(data, self.big_endian).read_value(nodes, &subtree_ends, 0)
+    }
+
+    /// The object's `m_Name`, for object types that start with one.
+    pub fn object_name(&self, object: &ObjectInfo) -> Option<String> {
+        let starts_with_name = match self.type_tree(object) {
+            Some(nodes) => nodes
+                .iter()
+                .find(|node| node.level == 1)
+                .is_some_and(|node| node.name == "m_Name" && node.type_name == "string"),
+            None => NAMED_CLASSES.contains(&object.class_id),
+        };
+        if !starts_with_name {
+            return None;
+        }
+        let mut reader = Reader::new(self.object_data(object)?, self.big_endian);
+        reader.read_aligned_string().ok()
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8], big_endian: bool) -> Self {
+        Self {
+            data,
+            position: 0,
+            big_endian,
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| {
+                BundleError::InvalidData("Unexpected end of SerializedFile".to_owned())
+            })?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        buf.copy_from_slice(self.read_bytes(N)?);
+        if !self.big_endian {
+            buf.reverse();
+        }
+        Ok(buf)
+    }
+
+    fn skip(&mut self, len: usize) -> Result<()> {
+        self.read_bytes(len).map(|_| ())
+    }
+
+    fn align(&mut self, alignment: usize) -> Result<()> {
+        let padding = (alignment - self.position % alignment) % alignment;
+        self.skip(padding.min(self.data.len() - self.position))
+    }
+
+    fn read_u8(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.read_array()?))
+    }
+
+    /// Reads an `i32` element count, rejecting counts that couldn't fit in
+    /// the remaining bytes given each element's minimum size.
+    fn read_f32(&mut self) -> Result<f32> {
+        Ok(f32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64> {
+        Ok(f64::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_count(&mut self, min_element_size: usize) -> Result<usize> {
+        let count = self.read_i32()?;
+        usize::try_from(count)
+            .ok()
+            .filter(|count| {
+                count.saturating_mul(min_element_size) <= self.data.len() - self.position
+            })
+            .ok_or_else(|| BundleError::InvalidData(format!("Implausible element count {}", count)))
+    }
+
+    fn read_cstring(&mut self) -> Result<String> {
+        let rest = &self.data[self.position..];
+        let len = rest.iter().position(|b| *b == 0).ok_or_else(|| {
+            BundleError::InvalidData("Unterminated string in SerializedFile".to_owned())
+        })?;
+        let bytes = self.read_bytes(len + 1)?;
+        Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+    }
+
+    fn read_aligned_string(&mut self) -> Result<String> {
+        let value = self.read_aligned_string_unpadded()?;
+        self.align(4)?;
+        Ok(value)
+    }
+
+    fn read_aligned_string_unpadded(&mut self) -> Result<String> {
+        let len = self.read_count(1)?;
+        let bytes = self.read_bytes(len)?;
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    /// Reads the value described by `nodes[index]` and its children.
+    /// `subtree_ends[i]` is one past the last descendant of node `i`.
+    fn read_value(
+        &mut self,
+        nodes: &[TypeTreeNode],
+        subtree_ends: &[usize],
+        index: usize,
+    ) -> Result<Value> {
+        let node = &nodes[index];
+        let children = || {
+            let mut child = index + 1;
+            std::iter::from_fn(move || {
+                (child < subtree_ends[index]).then(|| {
+                    let current = child;
+                    child = subtree_ends[current];
+                    current
+                })
+            })
+        };
+
+        let value = match node.type_name.as_str() {
+            "bool" => Value::Bool(self.read_u8()? != 0),
+            "SInt8" => Value::Int(self.read_u8()? as i8 as i64),
+            "UInt8" | "char" => Value::Int(self.read_u8()? as i64),
+            "SInt16" | "short" => Value::Int(self.read_i16()? as i64),
+            "UInt16" | "unsigned short" => Value::Int(self.read_u16()? as i64),
+            "SInt32" | "int" | "Type*" => Value::Int(self.read_i32()? as i64),
+            "UInt32" | "unsigned int" => Value::Int(self.read_u32()? as i64),
+            "SInt64" | "long long" | "FileSize" => Value::Int(self.read_i64()?),
+            "UInt64" | "unsigned long long" => Value::Int(self.read_u64()? as i64),
+            "float" => Value::Float(self.read_f32()? as f64),
+            "double" => Value::Float(self.read_f64()?),
+            "string" => {
+                let value = Value::String(self.read_aligned_string_unpadded()?);
+                if children().any(|child| nodes[child].meta_flag & ALIGN_BYTES_FLAG != 0) {
+                    self.align(4)?;
+                }
+                value
+            }
+            // Both hold a `size` child followed by the element type
+            "Array" | "TypelessData" => {
+                let element = children().nth(1).ok_or_else(|| {
+                    BundleError::InvalidData(format!("Array {} has no element type", node.name))
+                })?;
+                let element_node = &nodes[element];
+                let is_plain = subtree_ends[element] == element + 1 && element_node.byte_size > 0;
+                if is_plain {
+                    let len = self.read_count(element_node.byte_size as usize)?;
+                    self.skip(len * element_node.byte_size as usize)?;
+                    Value::Data(len)
+                } else {
+                    let len = self.read_count(1)?;
+                    let mut values = Vec::with_capacity(len.min(1024));
+                    for _ in 0..len {
+                        values.push(self.read_value(nodes, subtree_ends, element)?);
+                    }
+                    Value::Array(values)
+                }
+            }
+            _ => {
+                let mut children = children().peekable();
+                match children.next() {
+                    // Containers such as `vector` wrap a single `Array` node
+                    Some(child)
+                        if nodes[child].type_name == "Array" && children.peek().is_none() =>
+                    {
+                        self.read_value(nodes, subtree_ends, child)?
+                    }
+                    first => {
+                        let mut fields = Vec::new();
+                        for child in first.into_iter().chain(children) {
+                            let value = self.read_value(nodes, subtree_ends, child)?;
+                            fields.push((nodes[child].name.clone(), value));
+                        }
+                        Value::Struct(fields)
+                    }
+                }
+            }
+        };
+        if node.meta_flag & ALIGN_BYTES_FLAG != 0 {
+            self.align(4)?;
+        }
+        Ok(value)
+    }
+
+    fn read_type(&mut self, version: u32, enable_type_tree: bool) -> Result<SerializedType> {
+        let class_id = self.read_i32()?;
+        if version >= 16 {
+            self.skip(1)?; // is stripped type
+        }
+        if version >= 17 {
+            self.read_i16()?; // script type index
+        }
+        if (version < 16 && class_id < 0) || (version >= 16 && class_id == 114) {
+            self.skip(16)?; // script id
+        }
+        self.skip(16)?; // old type hash
+
+        let mut nodes = Vec::new();
+        if enable_type_tree {
+            let node_count = self.read_count(24)?;
+            let string_buffer_size = self.read_count(1)?;
+            let node_size = if version >= 19 { 32 } else { 24 };
+            let node_bytes = self.read_bytes(node_count.saturating_mul(node_size))?;
+            let strings = self.read_bytes(string_buffer_size)?;
+
+            let mut node_reader = Reader::new(node_bytes, self.big_endian);
+            nodes.reserve(node_count);
+            for _ in 0..node_count {
+                node_reader.skip(2)?; // version
+                let level = node_reader.read_u8()?;
+                node_reader.skip(1)?; // type flags
+                let type_name = lookup_string(strings, node_reader.read_u32()?);
+                let name = lookup_string(strings, node_reader.read_u32()?);
+                let byte_size = node_reader.read_i32()?;
+                node_reader.skip(4)?; // index
+                let meta_flag = node_reader.read_i32()?;
+                if version >= 19 {
+                    node_reader.skip(8)?; // ref type hash
+                }
+                nodes.push(TypeTreeNode {
+                    level,
+                    type_name,
+                    name,
+                    byte_size,
+                    meta_flag,
+                });
+            }
+
+            if version >= 21 {
+                let dependency_count = self.read_count(4)?;
+                self.skip(dependency_count * 4)?;
+            }
+        }
+
+        Ok(SerializedType { class_id, nodes })
+    }
+}
+
+/// For each node, the index one past its last descendant.
+fn subtree_ends(nodes: &[TypeTreeNode]) -> Vec<usize> {
+    let mut ends = vec![nodes.len(); nodes.len()];
+    let mut open: Vec<usize> = Vec::new();
+    for (index, node) in nodes.iter().enumerate() {
+        while let Some(&parent) = open.last() {
+            if nodes[parent].level < node.level {
+                break;
+            }
+            ends[parent] = index;
+            open.pop();
+        }
+        open.push(index);
+    }
+    ends
+}
+
+fn lookup_string(strings: &[u8], offset: u32) -> String {
+    let (buffer, offset) = if offset & 0x8000_0000 != 0 {
+        (COMMON_STRINGS.as_bytes(), (offset & 0x7FFF_FFFF) as usize)
+    } else {
+        (strings, offset as usize)
+    };
+    let rest = buffer.get(offset..).unwrap_or_default();
+    let len = rest.iter().position(|b| *b == 0).unwrap_or(rest.len());
+    String::from_utf8_lossy(&rest[..len]).into_owned()
+}
@@ -0,0 +1,165 @@
+//! Persisted user preferences, stored as JSON in the app data directory.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager, Runtime};
+
+use crate::bundle::{Compression, Platform};
+
+const TRANSCODE_DEFAULTS_FILE: &str = "transcode_defaults.json";
+
+/// Unity release the VRChat SDK built with when this version shipped, used
+/// until the user stores a newer one.
+pub const VRCHAT_UNITY_VERSION: &str = "2022.3.22f1";
+
+/// Settings `transcode_bundle` and `check_unity_version` fall back to when
+/// their arguments are omitted.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TranscodeDefaults {
+    pub target_compression: String,
+    pub lzma_preset: u32,
+    /// Uncompressed bytes per block, 0 to keep the input's blocks.
+    pub block_size: u32,
+    pub platform: String,
+    /// Unity version `check_unity_version` expects bundles to be built with,
+    /// kept here so it can follow SDK updates without a new release.
+    pub unity_version: String,
+}
+
+impl Default for TranscodeDefaults {
+    fn default() -> Self {
+        Self {
+            target_compression: "lzma".to_owned(),
+            lzma_preset: 6,
+            block_size: 0,
+            platform: "pc".to_owned(),
+            unity_version: VRCHAT_UNITY_VERSION.to_owned(),
+        }
+    }
+}
+
+impl TranscodeDefaults {
+    pub fn validate(&self) -> Result<(), String> {
+        // "keep" leaves each block's compression unchanged
+        if !self.target_compression.eq_ignore_ascii_case("keep") {
+            self.target_compression
+                .parse::<Compression>()
+                .map_err(|err| err.to_string())?;
+        }
+        if self.lzma_preset > 9 {
+            return Err(format!("LZMA preset {} is not in 0-9", self.lzma_preset));
+        }
+        validate_block_size(self.block_size)?;
+        self.platform
+            .parse::<Platform>()
+            .map_err(|err| err.to_string())?;
+        let mut parts = self.unity_version.split('.');
+        let release = parts.next().zip(parts.next());
+        if !release.is_some_and(|(year, minor)| {
+            year.parse::<u32>().is_ok() && minor.parse::<u32>().is_ok()
+        }) {
+            return Err(format!(
+                "Unity version {:?} is not like 2022.3.22f1",
+                self.unity_version
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Checks a block size as `transcode_bundle` takes it: 0 keeps the input's
+/// blocks, anything else must be 16 KiB - 64 MiB.
+pub fn validate_block_size(block_size: u32) -> Result<(), String> {
+    if block_size != 0 && !(16 * 1024..=64 * 1024 * 1024).contains(&block_size) {
+        return Err(format!(
+            "Block size {} is not between 16 KiB and 64 MiB",
+            block_size
+        ));
+    }
+    Ok(())
+}
+
+fn transcode_defaults_path<R: Runtime>(app: &AppHandle<R>) -> Result<PathBuf, String> {
+    let dir = app.path().app_data_dir().map_err(|e| e.to_string())?;
+    Ok(dir.join(TRANSCODE_DEFAULTS_FILE))
+}
+
+/// Loads the stored defaults, falling back to the built-in ones when the
+/// file is missing, unreadable or holds invalid settings.
+pub fn load_transcode_defaults<R: Runtime>(app: &AppHandle<R>) -> TranscodeDefaults {
+    transcode_defaults_path(app)
+        .ok()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|json| serde_json::from_slice::<TranscodeDefaults>(&json).ok())
+        .filter(|defaults| defaults.validate().is_ok())
+        .unwrap_or_default()
+}
+
+pub fn save_transcode_defaults<R: Runtime>(
+    app: &AppHandle<R>,
+    defaults: &TranscodeDefaults,
+) -> Result<(), String> {
+    defaults.validate()?;
+    let path = transcode_defaults_path(app)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    let json = serde_json::to_vec_pretty(defaults).map_err(|e| e.to_string())?;
+    std::fs::write(path, json).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_size_limits() {
+        assert!(validate_block_size(0).is_ok());
+        assert!(validate_block_size(16 * 1024).is_ok());
+        assert!(validate_block_size(64 * 1024 * 1024).is_ok());
+        assert!(validate_block_size(16 * 1024 - 1).is_err());
+        assert!(validate_block_size(64 * 1024 * 1024 + 1).is_err());
+
+        let defaults = TranscodeDefaults {
+            block_size: 1,
+            ..Default::default()
+        };
+        assert!(defaults.validate().is_err());
+    }
+
+    #[test]
+    fn block_size_is_read_from_stored_defaults() {
+        let json = br#"{"targetCompression":"lz4hc","blockSize":131072}"#;
+        let defaults: TranscodeDefaults = serde_json::from_slice(json).unwrap();
+        assert_eq!(defaults.block_size, 128 * 1024);
+        assert!(defaults.validate().is_ok());
+
+        // Files saved before the setting existed keep the input's blocks
+        let json = br#"{"targetCompression":"lz4hc","lzmaPreset":6,"platform":"pc"}"#;
+        let defaults: TranscodeDefaults = serde_json::from_slice(json).unwrap();
+        assert_eq!(defaults.block_size, 0);
+    }
+
+    #[test]
+    fn unity_version_setting() {
+        // Files saved before the setting existed expect the built-in version
+        let json = br#"{"targetCompression":"lz4hc","platform":"pc"}"#;
+        let defaults: TranscodeDefaults = serde_json::from_slice(json).unwrap();
+        assert_eq!(defaults.unity_version, VRCHAT_UNITY_VERSION);
+
+        let json = br#"{"unityVersion":"2022.3.58f1"}"#;
+        let defaults: TranscodeDefaults = serde_json::from_slice(json).unwrap();
+        assert_eq!(defaults.unity_version, "2022.3.58f1");
+        assert!(defaults.validate().is_ok());
+
+        for version in ["", "2022", "latest", "2022.x.1"] {
+            let defaults = TranscodeDefaults {
+                unity_version: version.to_owned(),
+                ..Default::default()
+            };
+            assert!(defaults.validate().is_err(), "{version}");
+        }
+    }
+}
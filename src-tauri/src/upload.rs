@@ -113,7 +113,7 @@ pub async fn upload(
 ) -> Result<String> {
     // Read the file
     let file = File::open(file_path).await?;
-    let file_len = file.metadata().await.unwrap().len();
+    let file_len = file.metadata().await?.len();
 
     // Create the request and attach the file to the body
     let client = reqwest::Client::new();